@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nes_emulator::memory::Memory;
+
+// Loading any file has to either succeed or return a RomError, it must never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = Memory::new(data);
+});
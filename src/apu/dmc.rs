@@ -0,0 +1,254 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// Timer periods of the DMC for NTSC, in CPU cycles
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// The delta modulation channel, which plays 1-bit delta encoded samples from PRG memory
+//
+// The channel cannot read memory by itself. When its sample buffer runs empty it requests a
+// DMA through `dma_address`, after which the CPU is stalled while the byte is fetched and handed
+// back through `fill_sample_buffer`. https://www.nesdev.org/wiki/APU_DMC
+#[derive(Debug, PartialEq)]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    interrupt_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    sample_address: u16, // Start address of the sample, $C000-$FFC0
+    sample_length: u16,  // Length of the sample in bytes
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8, // 7 bit output level, changed by +2 or -2 every output clock
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            interrupt_flag: false,
+            timer_period: DMC_RATE_TABLE[0],
+            timer: DMC_RATE_TABLE[0],
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+        }
+    }
+
+    // Writes to one of the channel registers, `register` is the address offset 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value >> 7 != 0;
+                self.looping = (value >> 6) & 1 != 0;
+                self.timer_period = DMC_RATE_TABLE[(value & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.interrupt_flag = false;
+                }
+            }
+            1 => self.output_level = value & 0x7F,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) + 1,
+        }
+    }
+
+    // Handles the DMC bit of a write to $4015, which also acknowledges the DMC interrupt
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.interrupt_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    // Returns true while there are bytes of the sample left to be fetched
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn interrupt_flag(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    // Returns the current output level, 0-127
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Returns the address the DMC wants to read when its sample buffer needs to be refilled
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // Completes a DMA started because of `dma_address`
+    pub fn fill_sample_buffer(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // The address wraps around to $8000 instead of $0000
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            address => address + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt_flag = true;
+            }
+        }
+    }
+
+    // Clocks the timer, the DMC is clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+
+        // Output unit
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        // Start a new output cycle with the next byte from the sample buffer
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.looping);
+        state.write_bool(self.interrupt_flag);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_bool(self.sample_buffer.is_some());
+        state.write_u8(self.sample_buffer.unwrap_or(0));
+        state.write_u8(self.shift_register);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence);
+        state.write_u8(self.output_level);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.irq_enabled = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.interrupt_flag = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        let buffered = state.read_bool()?;
+        let sample = state.read_u8()?;
+        self.sample_buffer = buffered.then_some(sample);
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence = state.read_bool()?;
+        self.output_level = state.read_u8()? & 0x7F;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dmc_sample_fetching() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0b1000_0000); // IRQ enabled, no loop
+        dmc.write(2, 0xFF); // $FFC0
+        dmc.write(3, 0x00); // 1 byte
+        assert_eq!(dmc.dma_address(), None);
+
+        dmc.set_enabled(true);
+        assert!(dmc.is_active());
+        assert_eq!(dmc.dma_address(), Some(0xFFC0));
+        dmc.fill_sample_buffer(0xFF);
+        assert_eq!(dmc.dma_address(), None);
+        assert!(!dmc.is_active());
+        assert!(dmc.interrupt_flag());
+
+        // Writing $4015 acknowledges the interrupt
+        dmc.set_enabled(false);
+        assert!(!dmc.interrupt_flag());
+    }
+
+    #[test]
+    fn test_dmc_address_wraps_to_8000() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0b0100_0000); // Looping
+        dmc.write(3, 0xFF);
+        dmc.set_enabled(true);
+        dmc.current_address = 0xFFFF;
+        dmc.fill_sample_buffer(0);
+        assert_eq!(dmc.current_address, 0x8000);
+    }
+
+    #[test]
+    fn test_dmc_output_unit() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x0F); // Fastest rate, 54 cycles per bit
+        dmc.timer = dmc.timer_period;
+        dmc.write(1, 0x40);
+        dmc.write(3, 0x00);
+        dmc.set_enabled(true);
+        dmc.fill_sample_buffer(0b0000_0011);
+
+        // The first output cycle is silent, after which the buffered sample is played
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output_level, 0x40);
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output_level, 0x40 + 4 - 12);
+    }
+}
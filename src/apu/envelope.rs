@@ -0,0 +1,100 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// The envelope generator used by the pulse and noise channels
+//
+// https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Debug, Default, PartialEq)]
+pub struct Envelope {
+    start: bool,    // Set when the fourth channel register is written
+    looping: bool,  // Shares its bit with the length counter halt flag
+    constant: bool, // Output the volume directly instead of the decay level
+    volume: u8,     // Constant volume, doubles as the divider period
+    divider: u8,    // Counts down to the next decay step
+    decay: u8,      // Current decay level, counts down from 15
+}
+
+impl Envelope {
+    // Writes the lower six bits of the first channel register (--LC VVVV)
+    pub fn write(&mut self, value: u8) {
+        self.looping = (value >> 5) & 1 != 0;
+        self.constant = (value >> 4) & 1 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Returns the current volume, 0-15
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    // Called on every quarter frame clock of the frame counter
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start);
+        state.write_bool(self.looping);
+        state.write_bool(self.constant);
+        state.write_u8(self.volume);
+        state.write_u8(self.divider);
+        state.write_u8(self.decay);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.start = state.read_bool()?;
+        self.looping = state.read_bool()?;
+        self.constant = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        self.decay = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_envelope_decay() {
+    let mut envelope = Envelope::default();
+    envelope.write(0b0000_0000); // Decaying, period 0
+    envelope.restart();
+    envelope.clock();
+    assert_eq!(envelope.decay, 15);
+    for _ in 0..15 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.decay, 0);
+
+    // Without the loop flag the decay level stays at 0
+    envelope.clock();
+    assert_eq!(envelope.decay, 0);
+
+    // With the loop flag the decay level wraps around to 15
+    envelope.write(0b0010_0000);
+    envelope.clock();
+    assert_eq!(envelope.decay, 15);
+
+    // With the constant volume flag the volume is output instead of the decay level
+    envelope.write(0b0001_0011);
+    assert_eq!(envelope.output(), 3);
+}
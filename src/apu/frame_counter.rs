@@ -0,0 +1,204 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// NTSC frame counter step positions, in CPU cycles since the sequence was reset
+//
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const FOUR_STEP_IRQ: u32 = 29828;
+const FOUR_STEP_4: u32 = 29829;
+const FOUR_STEP_RESET: u32 = 29830;
+const FIVE_STEP_5: u32 = 37281;
+const FIVE_STEP_RESET: u32 = 37282;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum FrameCounterMode {
+    FourStep,
+    FiveStep,
+}
+
+// The frame clocks generated by the frame counter in a single CPU cycle
+#[derive(Debug, Default, PartialEq)]
+pub struct FrameClock {
+    pub quarter: bool, // Clocks the envelopes and the triangle linear counter
+    pub half: bool,    // Clocks the length counters and sweep units
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FrameCounter {
+    mode: FrameCounterMode,
+    irq_inhibit: bool,
+    interrupt_flag: bool,
+    cycle: u32,              // CPU cycles since the sequence was last reset
+    reset_delay: Option<u8>, // CPU cycles until a write to $4017 resets the sequence
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            mode: FrameCounterMode::FourStep,
+            irq_inhibit: false,
+            interrupt_flag: false,
+            cycle: 0,
+            reset_delay: None,
+        }
+    }
+
+    // Writes the frame counter register $4017 (MI-- ----)
+    //
+    // The sequence is reset 3 CPU cycles after the write when it happens during an APU cycle, and
+    // 4 CPU cycles after the write when it happens between APU cycles.
+    pub fn write(&mut self, value: u8, odd_cycle: bool) {
+        self.mode = if value >> 7 != 0 {
+            FrameCounterMode::FiveStep
+        } else {
+            FrameCounterMode::FourStep
+        };
+        self.irq_inhibit = (value >> 6) & 1 != 0;
+        if self.irq_inhibit {
+            self.interrupt_flag = false;
+        }
+        self.reset_delay = Some(if odd_cycle { 4 } else { 3 });
+    }
+
+    pub fn interrupt_flag(&self) -> bool {
+        self.interrupt_flag
+    }
+
+    // Clears the interrupt flag, which happens when $4015 is read
+    pub fn clear_interrupt_flag(&mut self) {
+        self.interrupt_flag = false;
+    }
+
+    fn set_interrupt_flag(&mut self) {
+        if !self.irq_inhibit {
+            self.interrupt_flag = true;
+        }
+    }
+
+    // Advances the frame counter by one CPU cycle and returns the frame clocks for that cycle
+    pub fn tick(&mut self) -> FrameClock {
+        let mut clock = FrameClock::default();
+        self.cycle += 1;
+
+        match (self.mode, self.cycle) {
+            (_, STEP_1) | (_, STEP_3) => clock.quarter = true,
+            (_, STEP_2) => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            (FrameCounterMode::FourStep, FOUR_STEP_IRQ) => self.set_interrupt_flag(),
+            (FrameCounterMode::FourStep, FOUR_STEP_4) => {
+                clock.quarter = true;
+                clock.half = true;
+                self.set_interrupt_flag();
+            }
+            (FrameCounterMode::FourStep, FOUR_STEP_RESET) => {
+                self.set_interrupt_flag();
+                self.cycle = 0;
+            }
+            (FrameCounterMode::FiveStep, FIVE_STEP_5) => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            (FrameCounterMode::FiveStep, FIVE_STEP_RESET) => self.cycle = 0,
+            _ => (),
+        }
+
+        if let Some(delay) = self.reset_delay {
+            if delay <= 1 {
+                self.reset_delay = None;
+                self.cycle = 0;
+                // Resetting into the five step mode immediately clocks all units
+                if self.mode == FrameCounterMode::FiveStep {
+                    clock.quarter = true;
+                    clock.half = true;
+                }
+            } else {
+                self.reset_delay = Some(delay - 1);
+            }
+        }
+        clock
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.mode == FrameCounterMode::FiveStep);
+        state.write_bool(self.irq_inhibit);
+        state.write_bool(self.interrupt_flag);
+        state.write_u32(self.cycle);
+        state.write_bool(self.reset_delay.is_some());
+        state.write_u8(self.reset_delay.unwrap_or(0));
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mode = if state.read_bool()? {
+            FrameCounterMode::FiveStep
+        } else {
+            FrameCounterMode::FourStep
+        };
+        self.irq_inhibit = state.read_bool()?;
+        self.interrupt_flag = state.read_bool()?;
+        self.cycle = state.read_u32()?;
+        let delayed = state.read_bool()?;
+        let delay = state.read_u8()?;
+        self.reset_delay = delayed.then_some(delay);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ticks the frame counter and counts the generated quarter and half frame clocks
+    fn run(frame_counter: &mut FrameCounter, cycles: u32) -> (u32, u32) {
+        let mut quarters = 0;
+        let mut halves = 0;
+        for _ in 0..cycles {
+            let clock = frame_counter.tick();
+            quarters += clock.quarter as u32;
+            halves += clock.half as u32;
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut frame_counter = FrameCounter::new();
+        assert_eq!(run(&mut frame_counter, FOUR_STEP_IRQ - 1), (3, 1));
+        assert!(!frame_counter.interrupt_flag());
+        assert_eq!(run(&mut frame_counter, 1), (0, 0));
+        assert!(frame_counter.interrupt_flag());
+        assert_eq!(run(&mut frame_counter, 2), (1, 1));
+
+        // Reading $4015 clears the flag
+        frame_counter.clear_interrupt_flag();
+        assert_eq!(run(&mut frame_counter, STEP_1), (1, 0));
+        assert!(!frame_counter.interrupt_flag());
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut frame_counter = FrameCounter::new();
+        frame_counter.write(0b1000_0000, false);
+        // The reset after three cycles clocks all units once
+        assert_eq!(run(&mut frame_counter, 3), (1, 1));
+        assert_eq!(run(&mut frame_counter, FIVE_STEP_RESET), (4, 2));
+        assert!(!frame_counter.interrupt_flag());
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut frame_counter = FrameCounter::new();
+        run(&mut frame_counter, FOUR_STEP_4);
+        assert!(frame_counter.interrupt_flag());
+
+        // Setting the inhibit flag clears the interrupt flag and keeps it clear
+        frame_counter.write(0b0100_0000, true);
+        assert!(!frame_counter.interrupt_flag());
+        run(&mut frame_counter, FOUR_STEP_RESET);
+        assert!(!frame_counter.interrupt_flag());
+    }
+}
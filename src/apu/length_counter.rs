@@ -0,0 +1,88 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// Lengths loaded into the length counter, indexed by the upper five bits of the
+// fourth channel register. https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct LengthCounter {
+    enabled: bool, // Set through the channel bits of $4015
+    halt: bool,    // Stops the counter from counting down
+    counter: u8,
+}
+
+impl LengthCounter {
+    // Enables or disables the counter, disabling it also silences the channel immediately
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    // Reloads the counter from the length table, this is ignored while the channel is disabled
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    // Called on every half frame clock of the frame counter
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.halt);
+        state.write_u8(self.counter);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.halt = state.read_bool()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_length_counter() {
+    let mut length = LengthCounter::default();
+
+    // Loading while disabled is ignored
+    length.load(1);
+    assert!(!length.is_active());
+
+    length.set_enabled(true);
+    length.load(3); // Loads a length of 2
+    assert!(length.is_active());
+    length.clock();
+    length.clock();
+    assert!(!length.is_active());
+
+    // A halted counter keeps its value
+    length.load(3);
+    length.set_halt(true);
+    length.clock();
+    length.clock();
+    assert!(length.is_active());
+
+    // Disabling the channel clears the counter
+    length.set_enabled(false);
+    assert!(!length.is_active());
+}
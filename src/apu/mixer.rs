@@ -0,0 +1,31 @@
+// Mixes the outputs of the channels into a single sample between 0.0 and 1.0
+//
+// This uses the nonlinear formulas from https://www.nesdev.org/wiki/APU_Mixer, which model how
+// the outputs of the channels sharing a DAC influence each other.
+pub fn mix(pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = pulse_1 as f32 + pulse_2 as f32;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+
+    pulse_out + tnd_out
+}
+
+#[test]
+fn test_mix() {
+    assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+    assert!((mix(15, 15, 0, 0, 0) - 0.2585).abs() < 0.0001);
+    assert!((mix(0, 0, 15, 15, 127) - 0.7415).abs() < 0.0001);
+
+    // The pulse channels are not mixed linearly
+    assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+}
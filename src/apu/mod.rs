@@ -0,0 +1,252 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+use dmc::Dmc;
+use frame_counter::FrameCounter;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod mixer;
+mod noise;
+mod pulse;
+mod triangle;
+
+// The audio processing unit of the 2A03
+//
+// The APU registers are mapped to $4000-$4013, $4015 and $4017, see
+// https://www.nesdev.org/wiki/APU_registers
+#[derive(Debug, PartialEq)]
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    odd_cycle: bool, // The pulse and noise timers are clocked on every other CPU cycle
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse_1: Pulse::new(1),
+            pulse_2: Pulse::new(2),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            odd_cycle: false,
+        }
+    }
+
+    // Write to one of the APU registers
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..0x4004 => self.pulse_1.write(address - 0x4000, value),
+            0x4004..0x4008 => self.pulse_2.write(address - 0x4004, value),
+            0x4008..0x400C => self.triangle.write(address - 0x4008, value),
+            0x400C..0x4010 => self.noise.write(address - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write(address - 0x4010, value),
+            0x4015 => {
+                self.pulse_1.set_enabled(value & 0b0_0001 != 0);
+                self.pulse_2.set_enabled(value & 0b0_0010 != 0);
+                self.triangle.set_enabled(value & 0b0_0100 != 0);
+                self.noise.set_enabled(value & 0b0_1000 != 0);
+                self.dmc.set_enabled(value & 0b1_0000 != 0);
+            }
+            0x4017 => self.frame_counter.write(value, self.odd_cycle),
+            _ => log::debug!("ignored write to APU register 0x{:04X}", address),
+        }
+    }
+
+    // Reads the status register $4015 and acknowledges the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.clear_interrupt_flag();
+        status
+    }
+
+    // Returns the value of the status register $4015 without any side effects
+    pub fn peek_status(&self) -> u8 {
+        (self.pulse_1.is_active() as u8)
+            | (self.pulse_2.is_active() as u8) << 1
+            | (self.triangle.is_active() as u8) << 2
+            | (self.noise.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_counter.interrupt_flag() as u8) << 6
+            | (self.dmc.interrupt_flag() as u8) << 7
+    }
+
+    // The frame counter asserts the IRQ line of the CPU while its interrupt flag is set
+    pub fn frame_counter_irq(&self) -> bool {
+        self.frame_counter.interrupt_flag()
+    }
+
+    // The DMC asserts the IRQ line of the CPU when a sample has finished playing
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.interrupt_flag()
+    }
+
+    // Returns the address of the sample byte the DMC wants to fetch, if any
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    // Hands the sample byte fetched for the DMC to it
+    pub fn dmc_dma_complete(&mut self, value: u8) {
+        self.dmc.fill_sample_buffer(value);
+    }
+
+    // Returns the mixed output of all channels, between 0.0 and 1.0
+    pub fn output(&self) -> f32 {
+        mixer::mix(
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        )
+    }
+
+    // Advance the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+            self.noise.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        let clock = self.frame_counter.tick();
+        if clock.quarter {
+            self.pulse_1.clock_quarter_frame();
+            self.pulse_2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if clock.half {
+            self.pulse_1.clock_half_frame();
+            self.pulse_2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse_1.save_state(state);
+        self.pulse_2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        self.frame_counter.save_state(state);
+        state.write_bool(self.odd_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pulse_1.load_state(state)?;
+        self.pulse_2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter.load_state(state)?;
+        self.odd_cycle = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_reflects_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_1111);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400B, 0b0000_1000);
+        assert_eq!(apu.peek_status(), 0b0000_0101);
+
+        apu.write_register(0x4015, 0b0000_0001);
+        assert_eq!(apu.peek_status(), 0b0000_0001);
+    }
+
+    #[test]
+    fn test_length_counter_expires() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0001_1000); // Length index 3, a length of 2
+
+        // Two half frame clocks happen in the first frame of the four step sequence
+        for _ in 0..30000 {
+            apu.tick();
+        }
+        assert_eq!(apu.peek_status() & 1, 0);
+    }
+
+    #[test]
+    fn test_read_status_clears_frame_interrupt() {
+        let mut apu = Apu::new();
+        for _ in 0..29830 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert_eq!(apu.peek_status() & 0x40, 0);
+    }
+
+    #[test]
+    fn test_dmc_status_and_interrupt() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0b1000_0000);
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(apu.peek_status(), 0b0001_0000);
+        assert_eq!(apu.dmc_dma_address(), Some(0xC000));
+
+        apu.dmc_dma_complete(0);
+        assert_eq!(apu.peek_status(), 0b1000_0000);
+        assert!(apu.dmc_irq());
+
+        // Unlike the frame interrupt, reading $4015 does not acknowledge the DMC interrupt
+        apu.read_status();
+        assert!(apu.dmc_irq());
+        apu.write_register(0x4015, 0);
+        assert!(!apu.dmc_irq());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0001_1111);
+        apu.write_register(0x4000, 0b1011_0101);
+        apu.write_register(0x4001, 0b1010_1001);
+        apu.write_register(0x4007, 0b0001_1010);
+        apu.write_register(0x4008, 0b1100_0011);
+        apu.write_register(0x400E, 0b1000_0100);
+        apu.write_register(0x4010, 0b0100_1111);
+        apu.write_register(0x4017, 0b1000_0000);
+        for _ in 0..12345 {
+            apu.tick();
+        }
+        apu.dmc_dma_complete(0x5A);
+
+        let mut state = StateWriter::new();
+        apu.save_state(&mut state);
+        let state = state.into_bytes();
+        let mut loaded = Apu::new();
+        let mut reader = StateReader::new(&state);
+        loaded.load_state(&mut reader).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(loaded, apu);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// Timer periods of the noise channel for NTSC, in APU cycles
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// The pseudo-random noise channel
+//
+// https://www.nesdev.org/wiki/APU_Noise
+#[derive(Debug, PartialEq)]
+pub struct Noise {
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    short_mode: bool, // Feed back bit 6 instead of bit 1, giving a shorter sequence
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16, // 15 bit linear feedback shift register
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            short_mode: false,
+            timer_period: NOISE_PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 1, // The shift register is loaded with 1 on power-up
+        }
+    }
+}
+
+impl Noise {
+    // Writes to one of the channel registers, `register` is the address offset 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length_counter.set_halt((value >> 5) & 1 != 0);
+                self.envelope.write(value);
+            }
+            1 => {} // Unused
+            2 => {
+                self.short_mode = value >> 7 != 0;
+                self.timer_period = NOISE_PERIOD_TABLE[(value & 0x0F) as usize];
+            }
+            _ => {
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Returns the current output of the channel, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active() || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    // Clocks the timer, the noise channel is clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.length_counter.save_state(state);
+        state.write_bool(self.short_mode);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u16(self.shift_register);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.envelope.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        self.short_mode = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.shift_register = state.read_u16()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_noise_shift_register() {
+    let mut noise = Noise::default();
+    noise.clock_timer();
+    // Bit 0 and bit 1 of 0b1 differ, so a 1 is shifted into bit 14
+    assert_eq!(noise.shift_register, 0b100_0000_0000_0000);
+
+    let mut noise = Noise::default();
+    noise.write(2, 0b1000_0000); // Short mode
+    noise.clock_timer();
+    assert_eq!(noise.shift_register, 0b100_0000_0000_0000);
+    noise.clock_timer(); // The timer has to count down first
+    assert_eq!(noise.shift_register, 0b100_0000_0000_0000);
+}
@@ -0,0 +1,268 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// The waveforms of the four duty cycles, 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// The sweep unit periodically adjusts the timer period of a pulse channel
+//
+// https://www.nesdev.org/wiki/APU_Sweep
+#[derive(Debug, Default, PartialEq)]
+pub struct Sweep {
+    enabled: bool,
+    period: u8, // Divider period, in half frames
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+    ones_complement: bool, // Pulse 1 subtracts one more than pulse 2 when negating
+}
+
+impl Sweep {
+    fn new(ones_complement: bool) -> Self {
+        Sweep {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    // Writes the sweep register (EPPP NSSS)
+    fn write(&mut self, value: u8) {
+        self.enabled = value >> 7 != 0;
+        self.period = (value >> 4) & 0b111;
+        self.negate = (value >> 3) & 1 != 0;
+        self.shift = value & 0b111;
+        self.reload = true;
+    }
+
+    // Calculates the period the sweep unit is moving towards
+    fn target_period(&self, timer_period: u16) -> u16 {
+        let change = timer_period >> self.shift;
+        if self.negate {
+            let change = change + self.ones_complement as u16;
+            timer_period.saturating_sub(change)
+        } else {
+            timer_period + change
+        }
+    }
+
+    // The channel is muted when the current period is too low or the target period overflows,
+    // regardless of whether the sweep unit is enabled
+    fn is_muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target_period(timer_period) > 0x7FF
+    }
+
+    // Called on every half frame clock, returns the new timer period
+    fn clock(&mut self, timer_period: u16) -> u16 {
+        let mut new_period = timer_period;
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.is_muting(timer_period) {
+            new_period = self.target_period(timer_period);
+        }
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+        new_period
+    }
+
+    // The negate behaviour is fixed by the channel and not part of the state
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.period);
+        state.write_bool(self.negate);
+        state.write_u8(self.shift);
+        state.write_bool(self.reload);
+        state.write_u8(self.divider);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u8()?;
+        self.negate = state.read_bool()?;
+        self.shift = state.read_u8()?;
+        self.reload = state.read_bool()?;
+        self.divider = state.read_u8()?;
+        Ok(())
+    }
+}
+
+// One of the two square wave channels
+//
+// https://www.nesdev.org/wiki/APU_Pulse
+#[derive(Debug, PartialEq)]
+pub struct Pulse {
+    envelope: Envelope,
+    sweep: Sweep,
+    length_counter: LengthCounter,
+    duty: u8,          // Index into the duty table
+    timer_period: u16, // 11 bit period, in APU cycles
+    timer: u16,
+    sequence_step: u8, // Position in the 8 step duty sequence
+}
+
+impl Pulse {
+    // Creates pulse channel 1 or 2, they only differ in how the sweep unit negates
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            envelope: Envelope::default(),
+            sweep: Sweep::new(channel == 1),
+            length_counter: LengthCounter::default(),
+            duty: 0,
+            timer_period: 0,
+            timer: 0,
+            sequence_step: 0,
+        }
+    }
+
+    // Writes to one of the four channel registers, `register` is the address offset 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.set_halt((value >> 5) & 1 != 0);
+                self.envelope.write(value);
+            }
+            1 => self.sweep.write(value),
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.load(value >> 3);
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Returns the current output of the channel, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active()
+            || self.sweep.is_muting(self.timer_period)
+            || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    // Clocks the timer, the pulse channels are clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        self.timer_period = self.sweep.clock(self.timer_period);
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.envelope.save_state(state);
+        self.sweep.save_state(state);
+        self.length_counter.save_state(state);
+        state.write_u8(self.duty);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u8(self.sequence_step);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.envelope.load_state(state)?;
+        self.sweep.load_state(state)?;
+        self.length_counter.load_state(state)?;
+        self.duty = state.read_u8()? & 0b11;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.sequence_step = state.read_u8()? & 0b111;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut sweep_1 = Sweep::new(true);
+        let mut sweep_2 = Sweep::new(false);
+        sweep_1.write(0b1000_1001); // Enabled, period 0, negate, shift 1
+        sweep_2.write(0b1000_1001);
+
+        // Pulse 1 subtracts the change and one more, pulse 2 only the change
+        assert_eq!(sweep_1.target_period(0x100), 0x7F);
+        assert_eq!(sweep_2.target_period(0x100), 0x80);
+        assert_eq!(sweep_1.clock(0x100), 0x7F);
+        assert_eq!(sweep_2.clock(0x100), 0x80);
+    }
+
+    #[test]
+    fn test_sweep_muting() {
+        let mut sweep = Sweep::new(false);
+        sweep.write(0b0000_0001); // Disabled, shift 1
+        assert!(sweep.is_muting(7));
+        assert!(!sweep.is_muting(0x500));
+        assert!(sweep.is_muting(0x600)); // 0x600 + 0x300 overflows 11 bits
+
+        // A muting sweep unit does not change the period, even when enabled
+        sweep.write(0b1000_0001);
+        assert_eq!(sweep.clock(0x600), 0x600);
+    }
+
+    #[test]
+    fn test_pulse_length_reload() {
+        let mut pulse = Pulse::new(1);
+        pulse.write(3, 0b0000_1000); // Length index 1, ignored while disabled
+        assert!(!pulse.is_active());
+
+        pulse.set_enabled(true);
+        pulse.write(3, 0b0000_1000);
+        assert!(pulse.is_active());
+
+        pulse.set_enabled(false);
+        assert!(!pulse.is_active());
+    }
+
+    #[test]
+    fn test_pulse_output() {
+        let mut pulse = Pulse::new(2);
+        pulse.set_enabled(true);
+        pulse.write(0, 0b1001_1010); // 50% duty, constant volume 10
+        pulse.write(2, 0x10);
+        pulse.write(3, 0b0000_1000);
+
+        // The first step of the 50% duty cycle is low
+        assert_eq!(pulse.output(), 0);
+        pulse.clock_timer();
+        assert_eq!(pulse.output(), 10);
+
+        // Periods below 8 are muted by the sweep unit
+        pulse.write(2, 0x07);
+        assert_eq!(pulse.output(), 0);
+    }
+}
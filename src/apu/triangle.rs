@@ -0,0 +1,131 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// The 32 step sequence of the triangle wave
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// The triangle wave channel
+//
+// https://www.nesdev.org/wiki/APU_Triangle
+#[derive(Debug, Default, PartialEq)]
+pub struct Triangle {
+    length_counter: LengthCounter,
+    control: bool, // Halts the length counter and keeps the linear counter reloading
+    linear_reload_value: u8, // Value loaded into the linear counter
+    linear_counter: u8,
+    linear_reload: bool, // Set by writing the fourth channel register
+    timer_period: u16,   // 11 bit period, in CPU cycles
+    timer: u16,
+    sequence_step: u8, // Position in the 32 step triangle sequence
+}
+
+impl Triangle {
+    // Writes to one of the channel registers, `register` is the address offset 0-3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value >> 7 != 0;
+                self.length_counter.set_halt(self.control);
+                self.linear_reload_value = value & 0x7F;
+            }
+            1 => {} // Unused
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.load(value >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Returns the current output of the channel, 0-15
+    //
+    // Silencing the channel only stops the sequencer, so the output stays at its last value.
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_step as usize]
+    }
+
+    // Clocks the timer, the triangle channel is clocked every CPU cycle
+    //
+    // The sequencer only advances while both the length and linear counter are non-zero.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.length_counter.save_state(state);
+        state.write_bool(self.control);
+        state.write_u8(self.linear_reload_value);
+        state.write_u8(self.linear_counter);
+        state.write_bool(self.linear_reload);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u8(self.sequence_step);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.length_counter.load_state(state)?;
+        self.control = state.read_bool()?;
+        self.linear_reload_value = state.read_u8()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_reload = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.sequence_step = state.read_u8()? & 0b1_1111;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_triangle_linear_counter() {
+    let mut triangle = Triangle::default();
+    triangle.set_enabled(true);
+    triangle.write(0, 0b0000_0010); // Control clear, reload value 2
+    triangle.write(3, 0b0000_1000);
+
+    triangle.clock_quarter_frame();
+    assert_eq!(triangle.linear_counter, 2);
+    assert!(!triangle.linear_reload);
+    triangle.clock_quarter_frame();
+    triangle.clock_quarter_frame();
+    triangle.clock_quarter_frame();
+    assert_eq!(triangle.linear_counter, 0);
+
+    // The sequencer does not move while the linear counter is zero
+    triangle.clock_timer();
+    assert_eq!(triangle.sequence_step, 0);
+}
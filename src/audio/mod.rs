@@ -0,0 +1,62 @@
+use resampler::Resampler;
+pub use ring_buffer::{ring_buffer, Consumer, Producer};
+use std::fmt::Debug;
+use tudelft_nes_ppu::CPU_FREQ;
+pub use wav::WavSink;
+
+mod resampler;
+mod ring_buffer;
+mod wav;
+
+// Sample rates that sound devices commonly run at
+pub const SAMPLE_RATE_44100: u32 = 44100;
+pub const SAMPLE_RATE_48000: u32 = 48000;
+
+// Receives the resampled audio of the emulator
+//
+// A frontend with a sound device uses the producer of a ring buffer and drains the consumer
+// from its audio callback, while a headless run can handle the samples directly.
+pub trait AudioSink: Send + Debug {
+    // Called with every sample at the sample rate of the output, roughly between -1.0 and 1.0
+    fn write_sample(&mut self, sample: f32);
+}
+
+impl AudioSink for Producer {
+    fn write_sample(&mut self, sample: f32) {
+        // When the frontend does not keep up the sample is dropped rather than blocking the emulator
+        self.push(sample);
+    }
+}
+
+// The audio path from the APU to a sink
+#[derive(Debug)]
+pub struct AudioOutput {
+    resampler: Resampler,
+    sink: Box<dyn AudioSink>,
+}
+
+impl AudioOutput {
+    pub fn new(sample_rate: u32, sink: Box<dyn AudioSink>) -> Self {
+        AudioOutput {
+            resampler: Resampler::new(CPU_FREQ, sample_rate),
+            sink,
+        }
+    }
+
+    // Pushes the APU output of a single CPU cycle
+    pub fn push(&mut self, sample: f32) {
+        if let Some(sample) = self.resampler.push(sample) {
+            self.sink.write_sample(sample);
+        }
+    }
+}
+
+#[test]
+fn test_audio_output_to_ring_buffer() {
+    let (producer, consumer) = ring_buffer(4096);
+    let mut output = AudioOutput::new(SAMPLE_RATE_44100, Box::new(producer));
+    for _ in 0..CPU_FREQ as usize / 100 {
+        output.push(1.0);
+    }
+    assert!((440..=441).contains(&consumer.len()));
+}
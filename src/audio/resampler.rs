@@ -0,0 +1,67 @@
+use std::f32::consts::PI;
+
+// Cut-off frequency of the high-pass filter removing the DC offset of the mixer output
+const HIGH_PASS_FREQUENCY: f32 = 37.0;
+
+// Downsamples the output of the APU, which produces a sample every CPU cycle, to the sample
+// rate of the host
+//
+// Every output sample is the average of the input samples since the previous one, which also
+// acts as a (crude) low-pass filter against aliasing. The result is passed through a high-pass
+// filter, centering the unsigned mixer output around zero like the capacitors of the NES do.
+#[derive(Debug)]
+pub struct Resampler {
+    ratio: f64, // Output samples per input sample
+    phase: f64, // Fraction of an output sample accumulated so far
+    sum: f32,
+    count: u32,
+    high_pass_factor: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: u32) -> Self {
+        Resampler {
+            ratio: output_rate as f64 / input_rate,
+            phase: 0.0,
+            sum: 0.0,
+            count: 0,
+            high_pass_factor: (-2.0 * PI * HIGH_PASS_FREQUENCY / output_rate as f32).exp(),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    // Adds an input sample, returns an output sample when one is due
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        self.sum += sample;
+        self.count += 1;
+        self.phase += self.ratio;
+        if self.phase < 1.0 {
+            return None;
+        }
+        self.phase -= 1.0;
+
+        let average = self.sum / self.count as f32;
+        self.sum = 0.0;
+        self.count = 0;
+
+        let output = average - self.previous_input + self.high_pass_factor * self.previous_output;
+        self.previous_input = average;
+        self.previous_output = output;
+        Some(output)
+    }
+}
+
+#[test]
+fn test_resampler_rate() {
+    let mut resampler = Resampler::new(1_789_773.0, 48000);
+    let samples = (0..1_789_773)
+        .filter_map(|_| resampler.push(0.5))
+        .collect::<Vec<_>>();
+    assert!((47999..=48000).contains(&samples.len()));
+
+    // The high-pass filter removes a constant offset
+    assert!(samples.last().unwrap().abs() < 0.001);
+}
@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Storage shared between the two halves of a ring buffer
+//
+// The read and write positions only ever increase (wrapping around at usize::MAX), the
+// capacity is a power of two so they can be masked to get an index into the samples.
+#[derive(Debug)]
+struct Shared {
+    samples: Box<[AtomicU32]>, // The bits of f32 samples
+    read: AtomicUsize,         // Only written by the consumer
+    write: AtomicUsize,        // Only written by the producer
+}
+
+impl Shared {
+    fn mask(&self) -> usize {
+        self.samples.len() - 1
+    }
+}
+
+// Creates a lock-free single producer, single consumer ring buffer for audio samples
+//
+// The capacity is rounded up to the next power of two.
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity.max(1).next_power_of_two())
+            .map(|_| AtomicU32::new(0))
+            .collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+// The half of a ring buffer that the emulator pushes samples into
+#[derive(Debug)]
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    // Pushes a sample, returns false when the buffer is full and the sample was dropped
+    pub fn push(&mut self, sample: f32) -> bool {
+        let write = self.shared.write.load(Ordering::Relaxed);
+        let read = self.shared.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) == self.shared.samples.len() {
+            return false;
+        }
+        self.shared.samples[write & self.shared.mask()].store(sample.to_bits(), Ordering::Relaxed);
+        self.shared
+            .write
+            .store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+// The half of a ring buffer that a frontend drains, usually from an audio callback
+#[derive(Debug)]
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    pub fn pop(&mut self) -> Option<f32> {
+        let read = self.shared.read.load(Ordering::Relaxed);
+        let write = self.shared.write.load(Ordering::Acquire);
+        if read == write {
+            return None;
+        }
+        let sample = self.shared.samples[read & self.shared.mask()].load(Ordering::Relaxed);
+        self.shared
+            .read
+            .store(read.wrapping_add(1), Ordering::Release);
+        Some(f32::from_bits(sample))
+    }
+
+    // Returns the number of samples waiting in the buffer
+    pub fn len(&self) -> usize {
+        let write = self.shared.write.load(Ordering::Acquire);
+        write.wrapping_sub(self.shared.read.load(Ordering::Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_ring_buffer_full_and_empty() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+        assert!(consumer.is_empty());
+        for i in 0..4 {
+            assert!(producer.push(i as f32));
+        }
+        // The capacity was rounded up to 4
+        assert!(!producer.push(4.0));
+        assert_eq!(consumer.len(), 4);
+
+        assert_eq!(consumer.pop(), Some(0.0));
+        assert!(producer.push(4.0));
+        for i in 1..5 {
+            assert_eq!(consumer.pop(), Some(i as f32));
+        }
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn test_ring_buffer_across_threads() {
+        let (mut producer, mut consumer) = ring_buffer(16);
+        let handle = thread::spawn(move || {
+            for i in 0..10000 {
+                while !producer.push(i as f32) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < 10000 {
+            match consumer.pop() {
+                Some(sample) => {
+                    assert_eq!(sample, expected as f32);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        handle.join().unwrap();
+    }
+}
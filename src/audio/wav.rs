@@ -0,0 +1,108 @@
+use crate::audio::AudioSink;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+
+// The sizes in the header are updated this often (in samples), so a recording is still valid
+// when the emulator is closed without the sink being dropped
+const HEADER_UPDATE_INTERVAL: u32 = 4096;
+
+// Writes the audio to a mono 16-bit PCM WAV file
+#[derive(Debug)]
+pub struct WavSink<W: Write + Seek + Send + Debug> {
+    writer: W,
+    samples_written: u32,
+    failed: bool, // Set after an IO error, after which samples are discarded
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> io::Result<Self> {
+        WavSink::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek + Send + Debug> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?; // Size of the format chunk
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // Mono
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * 2).to_le_bytes())?; // Bytes per second
+        writer.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavSink {
+            writer,
+            samples_written: 0,
+            failed: false,
+        })
+    }
+
+    // Writes the current sizes into the header and flushes the file
+    pub fn finish(&mut self) -> io::Result<()> {
+        let data_size = self.samples_written * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+
+    fn write(&mut self, sample: f32) -> io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.writer.write_all(&value.to_le_bytes())?;
+        self.samples_written += 1;
+        if self.samples_written.is_multiple_of(HEADER_UPDATE_INTERVAL) {
+            self.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek + Send + Debug> AudioSink for WavSink<W> {
+    fn write_sample(&mut self, sample: f32) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.write(sample) {
+            log::error!("stopped recording audio: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+impl<W: Write + Seek + Send + Debug> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        if !self.failed {
+            if let Err(e) = self.finish() {
+                log::error!("could not finish audio recording: {}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_wav_sink() {
+    let mut file = Vec::new();
+    let mut sink = WavSink::new(io::Cursor::new(&mut file), 44100).unwrap();
+    sink.write_sample(0.0);
+    sink.write_sample(1.0);
+    sink.write_sample(-2.0); // Clipped
+    drop(sink);
+
+    assert_eq!(file.len(), 44 + 6);
+    assert_eq!(&file[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(file[4..8].try_into().unwrap()), 36 + 6);
+    assert_eq!(u32::from_le_bytes(file[24..28].try_into().unwrap()), 44100);
+    assert_eq!(u32::from_le_bytes(file[40..44].try_into().unwrap()), 6);
+    assert_eq!(&file[44..], &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+}
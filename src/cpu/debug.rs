@@ -1,3 +1,38 @@
+use crate::cpu::registers::StatusRegisterBit;
+use crate::cpu::watchpoints::{WatchKind, Watchpoint, Watchpoints};
+use crate::cpu::Cpu;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use tudelft_nes_ppu::Ppu;
+
+// Opcodes that return to the code that called or was interrupted
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+// Bytes shown by a memory dump without a length
+const DEFAULT_DUMP_LENGTH: u16 = 0x40;
+
+const HELP: &str = "\
+Addresses and values are hexadecimal, an empty line repeats the last command
+  s, step [N]           execute N instructions (1 by default)
+  n, next               step over a JSR, stopping when the subroutine returns
+  f, finish             run until the current subroutine returns with RTS or RTI
+  c, continue           run until a breakpoint
+  b, break [ADDR]       set a breakpoint, or list them without an address
+  d, delete ADDR        remove a breakpoint
+  watch KIND ADDR[-END] [VALUE]
+                        stop after an access to an address range, or list the watchpoints
+                        without arguments. KIND is r, w, rw or x, for read, write, both or
+                        execute. With a value, only accesses of that value or that opcode stop
+  unwatch N             remove watchpoint N
+  r, regs               show the registers and flags
+  m, mem ADDR [LEN]     dump LEN bytes of memory (40 by default)
+  set REG VALUE         change a, x, y, sp, pc or p, or one of the flags n, v, d, i, z, c
+  w, write ADDR VALUE.. write bytes to memory like a store instruction
+  h, help               show this text";
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum DebugMode {
@@ -5,3 +40,529 @@ pub enum DebugMode {
     Info,
     No,
 }
+
+// Where the instruction trace of `DebugMode::Emu` is written to
+pub(crate) struct Trace(pub Box<dyn Write + Send>);
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace").finish_non_exhaustive()
+    }
+}
+
+// Where execution stops again after the debugger lets it continue
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunMode {
+    Continue,  // Only at breakpoints
+    Step(u32), // After this many more instructions
+    StepOver {
+        return_address: u16, // Address after the JSR
+        stack_pointer: u8,   // Stack pointer before the JSR, deeper recursion does not stop
+    },
+    StepOut {
+        stack_pointer: u8, // Stack pointer in the subroutine, a return moves it above this
+    },
+}
+
+// A command typed at the debugger prompt
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Break(Option<u16>),
+    Delete(u16),
+    Watch(Option<Watchpoint>),
+    Unwatch(usize),
+    Registers,
+    Memory(u16, u16),
+    Set(String, u16),
+    Write(u16, Vec<u8>),
+    Help,
+}
+
+// An interactive debugger that stops the CPU before an instruction and reads commands
+//
+// The CPU asks the debugger at the start of every instruction whether it should stop. When it
+// does, the prompt runs inside `Cpu::tick` until a command lets execution continue, so the
+// emulation and the window are frozen in the meantime. It starts stopped at the first
+// instruction. When the input ends, the breakpoints are cleared and the emulator runs on.
+//
+// Watchpoints are told about accesses by the memory, they stop the CPU once the instruction
+// making the access is done, or before the instruction for an execute watchpoint.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Watchpoints,
+    mode: RunMode,
+    instruction_address: u16, // Address of the instruction that ran last
+    reasons: Vec<String>,     // Watchpoints that were hit, shown when the prompt opens
+    last_command: Option<Command>,
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("watchpoints", &self.watchpoints.list())
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debugger {
+    pub fn new(input: Box<dyn BufRead + Send>, output: Box<dyn Write + Send>) -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Watchpoints::default(),
+            mode: RunMode::Step(0),
+            instruction_address: 0,
+            reasons: Vec::new(),
+            last_command: None,
+            input,
+            output,
+        }
+    }
+
+    // A debugger on the terminal
+    pub fn stdio() -> Self {
+        Debugger::new(
+            Box::new(io::BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        )
+    }
+
+    // The watchpoints, which the memory has to report its accesses to
+    pub(crate) fn watchpoints(&self) -> Watchpoints {
+        self.watchpoints.clone()
+    }
+
+    // Returns true when execution should stop before the instruction at the program counter
+    pub(crate) fn should_break(&mut self, cpu: &Cpu) -> bool {
+        let program_counter = cpu.program_counter.get();
+        let stack_pointer = cpu.stack_pointer.get();
+        for hit in self.watchpoints.take_hits() {
+            self.reasons.push(format!(
+                "{} (last instruction at {:04X})",
+                hit, self.instruction_address
+            ));
+        }
+        let opcode = cpu.memory.read_cpu_mem(program_counter).unwrap_or(0);
+        for number in self.watchpoints.executed(program_counter, opcode) {
+            self.reasons.push(format!(
+                "Watchpoint {}: execute {:02X} at {:04X}",
+                number, opcode, program_counter
+            ));
+        }
+        self.instruction_address = program_counter;
+
+        let stop = match &mut self.mode {
+            RunMode::Continue => false,
+            RunMode::Step(0) => true,
+            RunMode::Step(remaining) => {
+                *remaining -= 1;
+                false
+            }
+            RunMode::StepOver {
+                return_address,
+                stack_pointer: called_from,
+            } => program_counter == *return_address && stack_pointer >= *called_from,
+            RunMode::StepOut {
+                stack_pointer: inside,
+            } => matches!(cpu.current_opcode, RTS | RTI) && stack_pointer > *inside,
+        };
+        stop || !self.reasons.is_empty() || self.breakpoints.contains(&program_counter)
+    }
+
+    // Shows where the CPU stopped and runs commands until one of them continues execution
+    pub(crate) fn prompt(&mut self, cpu: &mut Cpu, ppu: &mut Ppu) {
+        for reason in self.reasons.drain(..) {
+            let _ = writeln!(self.output, "{}", reason);
+        }
+        let _ = writeln!(self.output, "{}", location(cpu));
+        self.read_commands(cpu, ppu);
+        // writes made at the prompt don't stop on a watchpoint
+        self.watchpoints.take_hits();
+    }
+
+    fn read_commands(&mut self, cpu: &mut Cpu, ppu: &mut Ppu) {
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            if !matches!(self.input.read_line(&mut line), Ok(read) if read > 0) {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                self.mode = RunMode::Continue;
+                let _ = writeln!(self.output);
+                return;
+            }
+            let command = if line.trim().is_empty() {
+                match self.last_command.clone() {
+                    Some(command) => command,
+                    None => continue,
+                }
+            } else {
+                match parse_command(&line) {
+                    Ok(command) => command,
+                    Err(e) => {
+                        let _ = writeln!(self.output, "{}", e);
+                        continue;
+                    }
+                }
+            };
+            self.last_command = Some(command.clone());
+            if self.run(command, cpu, ppu) {
+                return;
+            }
+        }
+    }
+
+    // Runs a command, returns true when execution continues
+    fn run(&mut self, command: Command, cpu: &mut Cpu, ppu: &mut Ppu) -> bool {
+        let output = &mut self.output;
+        match command {
+            Command::Step(count) => {
+                self.mode = RunMode::Step(count.max(1) - 1);
+                return true;
+            }
+            Command::Next => {
+                let program_counter = cpu.program_counter.get();
+                self.mode = match cpu.memory.read_cpu_mem(program_counter) {
+                    Ok(JSR) => RunMode::StepOver {
+                        return_address: program_counter.wrapping_add(3),
+                        stack_pointer: cpu.stack_pointer.get(),
+                    },
+                    _ => RunMode::Step(0),
+                };
+                return true;
+            }
+            Command::Finish => {
+                self.mode = RunMode::StepOut {
+                    stack_pointer: cpu.stack_pointer.get(),
+                };
+                return true;
+            }
+            Command::Continue => {
+                self.mode = RunMode::Continue;
+                return true;
+            }
+            Command::Break(Some(address)) => {
+                self.breakpoints.insert(address);
+            }
+            Command::Break(None) if self.breakpoints.is_empty() => {
+                let _ = writeln!(output, "No breakpoints");
+            }
+            Command::Break(None) => {
+                for address in &self.breakpoints {
+                    let _ = writeln!(output, "{:04X}", address);
+                }
+            }
+            Command::Delete(address) => {
+                if !self.breakpoints.remove(&address) {
+                    let _ = writeln!(output, "No breakpoint at {:04X}", address);
+                }
+            }
+            Command::Watch(Some(watchpoint)) => {
+                let _ = writeln!(output, "{}", watchpoint);
+                self.watchpoints.add(watchpoint);
+            }
+            Command::Watch(None) => {
+                let watchpoints = self.watchpoints.list();
+                if watchpoints.is_empty() {
+                    let _ = writeln!(output, "No watchpoints");
+                }
+                for (number, watchpoint) in watchpoints {
+                    let _ = writeln!(output, "{}: {}", number, watchpoint);
+                }
+            }
+            Command::Unwatch(number) => {
+                if !self.watchpoints.remove(number) {
+                    let _ = writeln!(output, "No watchpoint {}", number);
+                }
+            }
+            Command::Registers => {
+                let _ = writeln!(output, "{}", registers(cpu));
+            }
+            Command::Memory(address, length) => {
+                let _ = write!(output, "{}", dump(cpu, address, length));
+            }
+            Command::Set(register, value) => {
+                if let Err(e) = set_register(cpu, &register, value) {
+                    let _ = writeln!(output, "{}", e);
+                }
+            }
+            Command::Write(address, values) => {
+                for (offset, value) in values.into_iter().enumerate() {
+                    let address = address.wrapping_add(offset as u16);
+                    if let Err(e) = cpu.memory.write(address, value, ppu) {
+                        let _ = writeln!(output, "Could not write to {:04X}: {}", address, e);
+                    }
+                }
+            }
+            Command::Help => {
+                let _ = writeln!(output, "{}", HELP);
+            }
+        }
+        false
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid number: {}", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::try_from(parse_number(text)?).map_err(|_| format!("Not a byte: {}", text))
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let arguments: Vec<&str> = words.collect();
+    let argument = |index: usize| {
+        arguments
+            .get(index)
+            .copied()
+            .ok_or(format!("{} expects more arguments", command))
+    };
+    let command = match command {
+        "s" | "step" => match arguments.first() {
+            Some(count) => Command::Step(
+                count
+                    .parse()
+                    .map_err(|_| format!("Invalid count: {}", count))?,
+            ),
+            None => Command::Step(1),
+        },
+        "n" | "next" => Command::Next,
+        "f" | "finish" => Command::Finish,
+        "c" | "continue" => Command::Continue,
+        "b" | "break" => match arguments.first() {
+            Some(address) => Command::Break(Some(parse_number(address)?)),
+            None => Command::Break(None),
+        },
+        "d" | "delete" => Command::Delete(parse_number(argument(0)?)?),
+        "watch" if arguments.is_empty() => Command::Watch(None),
+        "watch" => Command::Watch(Some(parse_watchpoint(&arguments)?)),
+        "unwatch" => {
+            let number = argument(0)?;
+            Command::Unwatch(
+                number
+                    .parse()
+                    .map_err(|_| format!("Invalid watchpoint number: {}", number))?,
+            )
+        }
+        "r" | "regs" => Command::Registers,
+        "m" | "mem" => {
+            let length = match arguments.get(1) {
+                Some(length) => parse_number(length)?,
+                None => DEFAULT_DUMP_LENGTH,
+            };
+            Command::Memory(parse_number(argument(0)?)?, length)
+        }
+        "set" => Command::Set(
+            argument(0)?.to_ascii_lowercase(),
+            parse_number(argument(1)?)?,
+        ),
+        "w" | "write" => {
+            argument(1)?;
+            let values = arguments[1..]
+                .iter()
+                .map(|value| parse_byte(value))
+                .collect::<Result<_, _>>()?;
+            Command::Write(parse_number(argument(0)?)?, values)
+        }
+        "h" | "help" => Command::Help,
+        _ => {
+            return Err(format!(
+                "Unknown command: {} (type help for a list)",
+                line.trim()
+            ))
+        }
+    };
+    Ok(command)
+}
+
+// Parses the arguments of `watch`, like `w 0075 00` or `rw 2000-2007`
+fn parse_watchpoint(arguments: &[&str]) -> Result<Watchpoint, String> {
+    let (kind, range, value) = match arguments {
+        [kind, range] => (kind, range, None),
+        [kind, range, value] => (kind, range, Some(parse_byte(value)?)),
+        _ => return Err("watch expects a kind, an address range and maybe a value".to_string()),
+    };
+    let kind = match *kind {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "rw" => WatchKind::Access,
+        "x" => WatchKind::Execute,
+        _ => {
+            return Err(format!(
+                "Unknown watchpoint kind: {} (expected r, w, rw or x)",
+                kind
+            ))
+        }
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+        None => (parse_number(range)?, parse_number(range)?),
+    };
+    if end < start {
+        return Err(format!("Address range {} ends before it starts", range));
+    }
+    Ok(Watchpoint {
+        kind,
+        start,
+        end,
+        value,
+    })
+}
+
+const FLAGS: [(char, StatusRegisterBit); 6] = [
+    ('n', StatusRegisterBit::Negative),
+    ('v', StatusRegisterBit::Overflow),
+    ('d', StatusRegisterBit::Decimal),
+    ('i', StatusRegisterBit::Interrupt),
+    ('z', StatusRegisterBit::Zero),
+    ('c', StatusRegisterBit::Carry),
+];
+
+fn set_register(cpu: &mut Cpu, register: &str, value: u16) -> Result<(), String> {
+    if register == "pc" {
+        cpu.program_counter.set(value);
+        return Ok(());
+    }
+    let byte = u8::try_from(value).map_err(|_| format!("Not a byte: {:X}", value))?;
+    match register {
+        "a" => cpu.accumulator.set(byte),
+        "x" => cpu.x_register.set(byte),
+        "y" => cpu.y_register.set(byte),
+        "sp" => cpu.stack_pointer.set(byte),
+        "p" => cpu.status_register.set_from_stack(byte),
+        _ => {
+            let (_, bit) = FLAGS
+                .iter()
+                .find(|(name, _)| register.len() == 1 && register.starts_with(*name))
+                .ok_or(format!("Unknown register: {}", register))?;
+            cpu.status_register.set_bit(*bit, byte != 0);
+        }
+    }
+    Ok(())
+}
+
+// The registers, with the flags as letters that are upper case when set
+fn registers(cpu: &Cpu) -> String {
+    let mut status = cpu.status_register;
+    let flags: String = FLAGS
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (name, bit))| {
+            let name = match status.get_bit(*bit) {
+                true => name.to_ascii_uppercase(),
+                false => *name,
+            };
+            // the break and unused bits sit between V and D
+            if index == 2 {
+                vec!['-', '-', name]
+            } else {
+                vec![name]
+            }
+        })
+        .collect();
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}",
+        cpu.accumulator.get(),
+        cpu.x_register.get(),
+        cpu.y_register.get(),
+        cpu.stack_pointer.get(),
+        cpu.status_register.get() & !(1 << 4),
+        flags,
+        cpu.total_cycles,
+    )
+}
+
+// The instruction at the program counter and the registers
+fn location(cpu: &Cpu) -> String {
+    let line = cpu.current_instruction();
+    format!(
+        "{:04X}  {:8}  {:13} {}",
+        line.address,
+        line.hex_bytes(),
+        line.to_string(),
+        registers(cpu)
+    )
+}
+
+// Memory as lines of 16 bytes, read without side effects
+fn dump(cpu: &Cpu, address: u16, length: u16) -> String {
+    let mut text = String::new();
+    for line_start in (0..length).step_by(16) {
+        let start = address.wrapping_add(line_start);
+        let bytes = (line_start..length.min(line_start.saturating_add(16)))
+            .map(|offset| {
+                let value = cpu
+                    .memory
+                    .read_cpu_mem(address.wrapping_add(offset))
+                    .unwrap_or(0);
+                format!("{:02X}", value)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        text.push_str(&format!("{:04X}: {}\n", start, bytes));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("s"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("step 10"), Ok(Command::Step(10)));
+        assert_eq!(parse_command("b $C000"), Ok(Command::Break(Some(0xC000))));
+        assert_eq!(parse_command("break"), Ok(Command::Break(None)));
+        assert_eq!(parse_command("m 0x10"), Ok(Command::Memory(0x10, 0x40)));
+        assert_eq!(parse_command("mem 200 8"), Ok(Command::Memory(0x200, 8)));
+        assert_eq!(
+            parse_command("set PC c004"),
+            Ok(Command::Set("pc".to_string(), 0xC004))
+        );
+        assert_eq!(
+            parse_command("w 10 1 ff"),
+            Ok(Command::Write(0x10, vec![0x01, 0xFF]))
+        );
+        assert!(parse_command("w 10 100").is_err());
+        assert!(parse_command("w 10").is_err());
+        assert!(parse_command("d").is_err());
+        assert_eq!(
+            parse_command("watch w $0075 0"),
+            Ok(Command::Watch(Some(Watchpoint {
+                kind: WatchKind::Write,
+                start: 0x75,
+                end: 0x75,
+                value: Some(0),
+            })))
+        );
+        assert_eq!(
+            parse_command("watch rw 2000-2007"),
+            Ok(Command::Watch(Some(Watchpoint {
+                kind: WatchKind::Access,
+                start: 0x2000,
+                end: 0x2007,
+                value: None,
+            })))
+        );
+        assert_eq!(parse_command("unwatch 2"), Ok(Command::Unwatch(2)));
+        assert!(parse_command("watch q 10").is_err());
+        assert!(parse_command("watch r 20-10").is_err());
+        assert!(parse_command("jump").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cpu::instructions::{AddressingMode, Instruction, InstructionType};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// The interrupt vectors at the end of the address space and the labels they get
+pub const VECTORS: [(u16, &str); 3] = [(0xFFFA, "nmi"), (0xFFFC, "reset"), (0xFFFE, "irq")];
+
+// An instruction, or data that could not be decoded, at an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,    // Like `LDA`, or `.db` and `.dw` for data
+    pub operand: String,     // Like `($20),Y`, empty when there is none
+    pub target: Option<u16>, // Address a jump, branch or vector goes to, which can get a label
+}
+
+impl Line {
+    // A byte that is not an instruction, or not a complete one
+    fn byte(address: u16, value: u8) -> Self {
+        Line {
+            address,
+            bytes: vec![value],
+            mnemonic: ".db".to_string(),
+            operand: format!("${:02X}", value),
+            target: None,
+        }
+    }
+
+    // A little endian address stored in the program, like a vector
+    fn word(address: u16, low: u8, high: u8) -> Self {
+        let value = u16::from_le_bytes([low, high]);
+        Line {
+            address,
+            bytes: vec![low, high],
+            mnemonic: ".dw".to_string(),
+            operand: format!("${:04X}", value),
+            target: Some(value),
+        }
+    }
+
+    // The bytes of the line in hexadecimal, like `4C F5 C5`
+    pub fn hex_bytes(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // The instruction with the label of its target in place of the address
+    pub fn with_labels(&self, labels: &BTreeMap<u16, String>) -> String {
+        let label = self.target.and_then(|target| labels.get(&target));
+        match label {
+            Some(label) => format!("{} {}", self.mnemonic, label),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+// Decodes the instruction at the start of `code`, which is at `address`
+//
+// Unknown opcodes, and instructions the code ends in the middle of, are returned as a byte of
+// data.
+pub fn disassemble_instruction(code: &[u8], address: u16) -> Line {
+    let Some(&opcode) = code.first() else {
+        return Line::byte(address, 0);
+    };
+    let Some(instruction) = Instruction::lookup(opcode) else {
+        return Line::byte(address, opcode);
+    };
+    let length = instruction.addressing_mode.length() as usize;
+    let Some(bytes) = code.get(..length) else {
+        return Line::byte(address, opcode);
+    };
+    let low = bytes.get(1).copied().unwrap_or(0);
+    let high = bytes.get(2).copied().unwrap_or(0);
+    let absolute = u16::from_le_bytes([low, high]);
+    let (operand, target) = match instruction.addressing_mode {
+        AddressingMode::Accumulator => ("A".to_string(), None),
+        AddressingMode::Implied => (String::new(), None),
+        AddressingMode::Immediate => (format!("#${:02X}", low), None),
+        AddressingMode::ZeroPage => (format!("${:02X}", low), None),
+        AddressingMode::ZeroPageX => (format!("${:02X},X", low), None),
+        AddressingMode::ZeroPageY => (format!("${:02X},Y", low), None),
+        AddressingMode::Absolute => {
+            let jump = matches!(
+                instruction.instruction_type,
+                InstructionType::JMP | InstructionType::JSR
+            );
+            (format!("${:04X}", absolute), jump.then_some(absolute))
+        }
+        AddressingMode::AbsoluteX => (format!("${:04X},X", absolute), None),
+        AddressingMode::AbsoluteY => (format!("${:04X},Y", absolute), None),
+        AddressingMode::Indirect => (format!("(${:04X})", absolute), None),
+        AddressingMode::IndirectX => (format!("(${:02X},X)", low), None),
+        AddressingMode::IndirectY => (format!("(${:02X}),Y", low), None),
+        AddressingMode::Relative => {
+            let target = address
+                .wrapping_add(length as u16)
+                .wrapping_add(low as i8 as u16);
+            (format!("${:04X}", target), Some(target))
+        }
+    };
+    Line {
+        address,
+        bytes: bytes.to_vec(),
+        mnemonic: format!("{:?}", instruction.instruction_type),
+        operand,
+        target,
+    }
+}
+
+// Decodes code from start to end, as if all of it were instructions
+pub fn disassemble(code: &[u8], origin: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let line = disassemble_instruction(&code[offset..], origin.wrapping_add(offset as u16));
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+// Decodes a bank of PRG ROM mapped at `origin`, starting at the address `from`
+//
+// A bank that ends at $FFFF holds the interrupt vectors in its last six bytes, they are shown as
+// addresses instead of instructions.
+pub fn disassemble_bank(bank: &[u8], origin: u16, from: u16) -> Vec<Line> {
+    let start = usize::from(from.saturating_sub(origin)).min(bank.len());
+    let has_vectors = usize::from(origin) + bank.len() == 0x10000 && bank.len() >= 6;
+    let code_end = if has_vectors {
+        bank.len() - 6
+    } else {
+        bank.len()
+    };
+    let mut lines = disassemble(
+        &bank[start..code_end.max(start)],
+        origin.wrapping_add(start as u16),
+    );
+    if has_vectors {
+        for offset in (code_end.max(start)..bank.len()).step_by(2) {
+            let address = origin.wrapping_add(offset as u16);
+            lines.push(match bank.get(offset + 1) {
+                Some(&high) => Line::word(address, bank[offset], high),
+                None => Line::byte(address, bank[offset]),
+            });
+        }
+    }
+    lines
+}
+
+// Labels for the addresses the interrupt vectors point to, read from a bank ending at $FFFF
+pub fn vector_labels(last_bank: &[u8]) -> BTreeMap<u16, String> {
+    let mut labels = BTreeMap::new();
+    let Some(vectors) = last_bank
+        .len()
+        .checked_sub(6)
+        .map(|start| &last_bank[start..])
+    else {
+        return labels;
+    };
+    for (i, (_, name)) in VECTORS.iter().enumerate() {
+        let target = u16::from_le_bytes([vectors[2 * i], vectors[2 * i + 1]]);
+        // vectors sharing a handler get one label with both names
+        labels
+            .entry(target)
+            .and_modify(|label: &mut String| *label = format!("{}_{}", label, name))
+            .or_insert_with(|| name.to_string());
+    }
+    labels
+}
+
+// Formats lines as a listing with addresses and bytes, putting labels before their addresses
+pub fn format_listing(lines: &[Line], labels: &BTreeMap<u16, String>) -> String {
+    let mut listing = String::new();
+    for line in lines {
+        if let Some(label) = labels.get(&line.address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        listing.push_str(&format!(
+            "{:04X}  {:8}  {}\n",
+            line.address,
+            line.hex_bytes(),
+            line.with_labels(labels)
+        ));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(code: &[u8], address: u16) -> String {
+        disassemble_instruction(code, address).to_string()
+    }
+
+    #[test]
+    fn test_operand_syntax() {
+        assert_eq!(text(&[0xA9, 0x10], 0xC000), "LDA #$10");
+        assert_eq!(text(&[0xB1, 0x20], 0xC000), "LDA ($20),Y");
+        assert_eq!(text(&[0xA1, 0x20], 0xC000), "LDA ($20,X)");
+        assert_eq!(text(&[0xB6, 0x20], 0xC000), "LDX $20,Y");
+        assert_eq!(text(&[0x9D, 0x00, 0x02], 0xC000), "STA $0200,X");
+        assert_eq!(text(&[0x6C, 0xFC, 0xFF], 0xC000), "JMP ($FFFC)");
+        assert_eq!(text(&[0x0A], 0xC000), "ASL A");
+        assert_eq!(text(&[0x60], 0xC000), "RTS");
+        // Branches are relative to the next instruction
+        assert_eq!(text(&[0xD0, 0xF0], 0xC100), "BNE $C0F2");
+        assert_eq!(text(&[0x10, 0x02], 0xC0F0), "BPL $C0F4");
+        // Truncated instructions and unknown opcodes are data
+        assert_eq!(text(&[0x4C, 0x00], 0xC000), ".db $4C");
+        assert_eq!(text(&[0x89, 0x00], 0xC000), ".db $89");
+    }
+
+    #[test]
+    fn test_bank_listing_with_vector_labels() {
+        let mut bank = vec![0xEA; 0x4000];
+        bank[0..3].copy_from_slice(&[0x20, 0x05, 0xC0]); // JSR $C005
+        bank[5] = 0x40; // RTI
+        bank[0x3FFA..].copy_from_slice(&[0x05, 0xC0, 0x00, 0xC0, 0x05, 0xC0]);
+
+        let labels = vector_labels(&bank);
+        assert_eq!(labels[&0xC000], "reset");
+        assert_eq!(labels[&0xC005], "nmi_irq");
+
+        let lines = disassemble_bank(&bank, 0xC000, 0xC000);
+        assert_eq!(lines.len(), 0x3FFA - 3 + 1 + 3);
+        let listing = format_listing(&lines, &labels);
+        assert!(listing.starts_with(
+            "reset:\nC000  20 05 C0  JSR nmi_irq\nC003  EA        NOP\nC004  EA        NOP\n\
+             nmi_irq:\nC005  40        RTI\n"
+        ));
+        assert!(listing.ends_with(
+            "FFFA  05 C0     .dw nmi_irq\nFFFC  00 C0     .dw reset\nFFFE  05 C0     .dw nmi_irq\n"
+        ));
+
+        let lines = disassemble_bank(&bank, 0xC000, 0xFFF8);
+        assert_eq!(lines[0].to_string(), "NOP");
+        assert_eq!(lines[2].to_string(), ".dw $C005");
+        // Banks that are not at the end of the address space have no vectors
+        assert_eq!(
+            disassemble_bank(&bank, 0x8000, 0xBFFA)[0].to_string(),
+            "ORA $C0"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cpu::{Cpu, StatusRegisterBit};
-use crate::MainError;
+use crate::error::MainError;
 use log::warn;
 use tudelft_nes_ppu::Ppu;
 
@@ -170,1203 +170,1209 @@ pub enum InstructionType {
 }
 
 impl Instruction {
+    // Decodes an opcode, unknown opcodes are executed as a NOP
     pub fn decode(opcode: u8) -> Result<Instruction, MainError> {
+        Ok(Self::lookup(opcode).unwrap_or_else(|| {
+            warn!("Unknown opcode: {:#X}", opcode);
+            Instruction {
+                instruction_type: InstructionType::NOP,
+                addressing_mode: AddressingMode::Implied,
+            }
+        }))
+    }
+
+    // Returns the instruction of an opcode, or None when it is not known
+    pub fn lookup(opcode: u8) -> Option<Instruction> {
         match opcode {
             //ADC
-            0x69 => Ok(Instruction {
+            0x69 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0x65 => Ok(Instruction {
+            0x65 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x75 => Ok(Instruction {
+            0x75 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x6D => Ok(Instruction {
+            0x6D => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x7D => Ok(Instruction {
+            0x7D => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0x79 => Ok(Instruction {
+            0x79 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0x61 => Ok(Instruction {
+            0x61 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0x71 => Ok(Instruction {
+            0x71 => Some(Instruction {
                 instruction_type: InstructionType::ADC,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //AND
-            0x29 => Ok(Instruction {
+            0x29 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0x25 => Ok(Instruction {
+            0x25 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x35 => Ok(Instruction {
+            0x35 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x2D => Ok(Instruction {
+            0x2D => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x3D => Ok(Instruction {
+            0x3D => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0x39 => Ok(Instruction {
+            0x39 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0x21 => Ok(Instruction {
+            0x21 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0x31 => Ok(Instruction {
+            0x31 => Some(Instruction {
                 instruction_type: InstructionType::AND,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //ASL
-            0x0A => Ok(Instruction {
+            0x0A => Some(Instruction {
                 instruction_type: InstructionType::ASL,
                 addressing_mode: AddressingMode::Accumulator,
             }),
-            0x06 => Ok(Instruction {
+            0x06 => Some(Instruction {
                 instruction_type: InstructionType::ASL,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x16 => Ok(Instruction {
+            0x16 => Some(Instruction {
                 instruction_type: InstructionType::ASL,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x0E => Ok(Instruction {
+            0x0E => Some(Instruction {
                 instruction_type: InstructionType::ASL,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x1E => Ok(Instruction {
+            0x1E => Some(Instruction {
                 instruction_type: InstructionType::ASL,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //BIT
-            0x24 => Ok(Instruction {
+            0x24 => Some(Instruction {
                 instruction_type: InstructionType::BIT,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x2C => Ok(Instruction {
+            0x2C => Some(Instruction {
                 instruction_type: InstructionType::BIT,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //Branch
-            0x10 => Ok(Instruction {
+            0x10 => Some(Instruction {
                 instruction_type: InstructionType::BPL,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0x30 => Ok(Instruction {
+            0x30 => Some(Instruction {
                 instruction_type: InstructionType::BMI,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0x50 => Ok(Instruction {
+            0x50 => Some(Instruction {
                 instruction_type: InstructionType::BVC,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0x70 => Ok(Instruction {
+            0x70 => Some(Instruction {
                 instruction_type: InstructionType::BVS,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0x90 => Ok(Instruction {
+            0x90 => Some(Instruction {
                 instruction_type: InstructionType::BCC,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0xB0 => Ok(Instruction {
+            0xB0 => Some(Instruction {
                 instruction_type: InstructionType::BCS,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0xD0 => Ok(Instruction {
+            0xD0 => Some(Instruction {
                 instruction_type: InstructionType::BNE,
                 addressing_mode: AddressingMode::Relative,
             }),
-            0xF0 => Ok(Instruction {
+            0xF0 => Some(Instruction {
                 instruction_type: InstructionType::BEQ,
                 addressing_mode: AddressingMode::Relative,
             }),
 
             //BRK
-            0x00 => Ok(Instruction {
+            0x00 => Some(Instruction {
                 instruction_type: InstructionType::BRK,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //CMP
-            0xC9 => Ok(Instruction {
+            0xC9 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xC5 => Ok(Instruction {
+            0xC5 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xD5 => Ok(Instruction {
+            0xD5 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xCD => Ok(Instruction {
+            0xCD => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xDD => Ok(Instruction {
+            0xDD => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0xD9 => Ok(Instruction {
+            0xD9 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0xC1 => Ok(Instruction {
+            0xC1 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0xD1 => Ok(Instruction {
+            0xD1 => Some(Instruction {
                 instruction_type: InstructionType::CMP,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //CPX
-            0xE0 => Ok(Instruction {
+            0xE0 => Some(Instruction {
                 instruction_type: InstructionType::CPX,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xE4 => Ok(Instruction {
+            0xE4 => Some(Instruction {
                 instruction_type: InstructionType::CPX,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xEC => Ok(Instruction {
+            0xEC => Some(Instruction {
                 instruction_type: InstructionType::CPX,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //CPY
-            0xC0 => Ok(Instruction {
+            0xC0 => Some(Instruction {
                 instruction_type: InstructionType::CPY,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xC4 => Ok(Instruction {
+            0xC4 => Some(Instruction {
                 instruction_type: InstructionType::CPY,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xCC => Ok(Instruction {
+            0xCC => Some(Instruction {
                 instruction_type: InstructionType::CPY,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //DEC
-            0xC6 => Ok(Instruction {
+            0xC6 => Some(Instruction {
                 instruction_type: InstructionType::DEC,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xD6 => Ok(Instruction {
+            0xD6 => Some(Instruction {
                 instruction_type: InstructionType::DEC,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xCE => Ok(Instruction {
+            0xCE => Some(Instruction {
                 instruction_type: InstructionType::DEC,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xDE => Ok(Instruction {
+            0xDE => Some(Instruction {
                 instruction_type: InstructionType::DEC,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //EOR
-            0x49 => Ok(Instruction {
+            0x49 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0x45 => Ok(Instruction {
+            0x45 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x55 => Ok(Instruction {
+            0x55 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x4D => Ok(Instruction {
+            0x4D => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x5D => Ok(Instruction {
+            0x5D => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0x59 => Ok(Instruction {
+            0x59 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0x41 => Ok(Instruction {
+            0x41 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0x51 => Ok(Instruction {
+            0x51 => Some(Instruction {
                 instruction_type: InstructionType::EOR,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //Flag
-            0x18 => Ok(Instruction {
+            0x18 => Some(Instruction {
                 instruction_type: InstructionType::CLC,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x38 => Ok(Instruction {
+            0x38 => Some(Instruction {
                 instruction_type: InstructionType::SEC,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x58 => Ok(Instruction {
+            0x58 => Some(Instruction {
                 instruction_type: InstructionType::CLI,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x78 => Ok(Instruction {
+            0x78 => Some(Instruction {
                 instruction_type: InstructionType::SEI,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xB8 => Ok(Instruction {
+            0xB8 => Some(Instruction {
                 instruction_type: InstructionType::CLV,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xD8 => Ok(Instruction {
+            0xD8 => Some(Instruction {
                 instruction_type: InstructionType::CLD,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xF8 => Ok(Instruction {
+            0xF8 => Some(Instruction {
                 instruction_type: InstructionType::SED,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //INC
-            0xE6 => Ok(Instruction {
+            0xE6 => Some(Instruction {
                 instruction_type: InstructionType::INC,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xF6 => Ok(Instruction {
+            0xF6 => Some(Instruction {
                 instruction_type: InstructionType::INC,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xEE => Ok(Instruction {
+            0xEE => Some(Instruction {
                 instruction_type: InstructionType::INC,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xFE => Ok(Instruction {
+            0xFE => Some(Instruction {
                 instruction_type: InstructionType::INC,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //JMP
-            0x4C => Ok(Instruction {
+            0x4C => Some(Instruction {
                 instruction_type: InstructionType::JMP,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x6C => Ok(Instruction {
+            0x6C => Some(Instruction {
                 instruction_type: InstructionType::JMP,
                 addressing_mode: AddressingMode::Indirect,
             }),
 
             //JSR
-            0x20 => Ok(Instruction {
+            0x20 => Some(Instruction {
                 instruction_type: InstructionType::JSR,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //LDA
-            0xA9 => Ok(Instruction {
+            0xA9 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xA5 => Ok(Instruction {
+            0xA5 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xB5 => Ok(Instruction {
+            0xB5 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xAD => Ok(Instruction {
+            0xAD => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xBD => Ok(Instruction {
+            0xBD => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0xB9 => Ok(Instruction {
+            0xB9 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0xA1 => Ok(Instruction {
+            0xA1 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0xB1 => Ok(Instruction {
+            0xB1 => Some(Instruction {
                 instruction_type: InstructionType::LDA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //LDX
-            0xA2 => Ok(Instruction {
+            0xA2 => Some(Instruction {
                 instruction_type: InstructionType::LDX,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xA6 => Ok(Instruction {
+            0xA6 => Some(Instruction {
                 instruction_type: InstructionType::LDX,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xB6 => Ok(Instruction {
+            0xB6 => Some(Instruction {
                 instruction_type: InstructionType::LDX,
                 addressing_mode: AddressingMode::ZeroPageY,
             }),
-            0xAE => Ok(Instruction {
+            0xAE => Some(Instruction {
                 instruction_type: InstructionType::LDX,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xBE => Ok(Instruction {
+            0xBE => Some(Instruction {
                 instruction_type: InstructionType::LDX,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
             //LDY
-            0xA0 => Ok(Instruction {
+            0xA0 => Some(Instruction {
                 instruction_type: InstructionType::LDY,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xA4 => Ok(Instruction {
+            0xA4 => Some(Instruction {
                 instruction_type: InstructionType::LDY,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xB4 => Ok(Instruction {
+            0xB4 => Some(Instruction {
                 instruction_type: InstructionType::LDY,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xAC => Ok(Instruction {
+            0xAC => Some(Instruction {
                 instruction_type: InstructionType::LDY,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xBC => Ok(Instruction {
+            0xBC => Some(Instruction {
                 instruction_type: InstructionType::LDY,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //LSR
-            0x4A => Ok(Instruction {
+            0x4A => Some(Instruction {
                 instruction_type: InstructionType::LSR,
                 addressing_mode: AddressingMode::Accumulator,
             }),
-            0x46 => Ok(Instruction {
+            0x46 => Some(Instruction {
                 instruction_type: InstructionType::LSR,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x56 => Ok(Instruction {
+            0x56 => Some(Instruction {
                 instruction_type: InstructionType::LSR,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x4E => Ok(Instruction {
+            0x4E => Some(Instruction {
                 instruction_type: InstructionType::LSR,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x5E => Ok(Instruction {
+            0x5E => Some(Instruction {
                 instruction_type: InstructionType::LSR,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //NOP
-            0xEA => Ok(Instruction {
+            0xEA => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //ORA
-            0x09 => Ok(Instruction {
+            0x09 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0x05 => Ok(Instruction {
+            0x05 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x15 => Ok(Instruction {
+            0x15 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x0D => Ok(Instruction {
+            0x0D => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x1D => Ok(Instruction {
+            0x1D => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0x19 => Ok(Instruction {
+            0x19 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0x01 => Ok(Instruction {
+            0x01 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0x11 => Ok(Instruction {
+            0x11 => Some(Instruction {
                 instruction_type: InstructionType::ORA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //Register Instructionsinstruction_type
-            0xAA => Ok(Instruction {
+            0xAA => Some(Instruction {
                 instruction_type: InstructionType::TAX,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x8A => Ok(Instruction {
+            0x8A => Some(Instruction {
                 instruction_type: InstructionType::TXA,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xCA => Ok(Instruction {
+            0xCA => Some(Instruction {
                 instruction_type: InstructionType::DEX,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xE8 => Ok(Instruction {
+            0xE8 => Some(Instruction {
                 instruction_type: InstructionType::INX,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xA8 => Ok(Instruction {
+            0xA8 => Some(Instruction {
                 instruction_type: InstructionType::TAY,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x98 => Ok(Instruction {
+            0x98 => Some(Instruction {
                 instruction_type: InstructionType::TYA,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x88 => Ok(Instruction {
+            0x88 => Some(Instruction {
                 instruction_type: InstructionType::DEY,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xC8 => Ok(Instruction {
+            0xC8 => Some(Instruction {
                 instruction_type: InstructionType::INY,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //ROL
-            0x2A => Ok(Instruction {
+            0x2A => Some(Instruction {
                 instruction_type: InstructionType::ROL,
                 addressing_mode: AddressingMode::Accumulator,
             }),
-            0x26 => Ok(Instruction {
+            0x26 => Some(Instruction {
                 instruction_type: InstructionType::ROL,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x36 => Ok(Instruction {
+            0x36 => Some(Instruction {
                 instruction_type: InstructionType::ROL,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x2E => Ok(Instruction {
+            0x2E => Some(Instruction {
                 instruction_type: InstructionType::ROL,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x3E => Ok(Instruction {
+            0x3E => Some(Instruction {
                 instruction_type: InstructionType::ROL,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //ROR
-            0x6A => Ok(Instruction {
+            0x6A => Some(Instruction {
                 instruction_type: InstructionType::ROR,
                 addressing_mode: AddressingMode::Accumulator,
             }),
-            0x66 => Ok(Instruction {
+            0x66 => Some(Instruction {
                 instruction_type: InstructionType::ROR,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x76 => Ok(Instruction {
+            0x76 => Some(Instruction {
                 instruction_type: InstructionType::ROR,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x6E => Ok(Instruction {
+            0x6E => Some(Instruction {
                 instruction_type: InstructionType::ROR,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x7E => Ok(Instruction {
+            0x7E => Some(Instruction {
                 instruction_type: InstructionType::ROR,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //RTI
-            0x40 => Ok(Instruction {
+            0x40 => Some(Instruction {
                 instruction_type: InstructionType::RTI,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //RTS
-            0x60 => Ok(Instruction {
+            0x60 => Some(Instruction {
                 instruction_type: InstructionType::RTS,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //SBC
-            0xE9 => Ok(Instruction {
+            0xE9 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::Immediate,
             }),
-            0xE5 => Ok(Instruction {
+            0xE5 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0xF5 => Ok(Instruction {
+            0xF5 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0xED => Ok(Instruction {
+            0xED => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0xFD => Ok(Instruction {
+            0xFD => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0xF9 => Ok(Instruction {
+            0xF9 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0xE1 => Ok(Instruction {
+            0xE1 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0xF1 => Ok(Instruction {
+            0xF1 => Some(Instruction {
                 instruction_type: InstructionType::SBC,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //STA
-            0x85 => Ok(Instruction {
+            0x85 => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x95 => Ok(Instruction {
+            0x95 => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x8D => Ok(Instruction {
+            0x8D => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::Absolute,
             }),
-            0x9D => Ok(Instruction {
+            0x9D => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
-            0x99 => Ok(Instruction {
+            0x99 => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
-            0x81 => Ok(Instruction {
+            0x81 => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::IndirectX,
             }),
-            0x91 => Ok(Instruction {
+            0x91 => Some(Instruction {
                 instruction_type: InstructionType::STA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
             //Stack Instructions
-            0x9A => Ok(Instruction {
+            0x9A => Some(Instruction {
                 instruction_type: InstructionType::TXS,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0xBA => Ok(Instruction {
+            0xBA => Some(Instruction {
                 instruction_type: InstructionType::TSX,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x48 => Ok(Instruction {
+            0x48 => Some(Instruction {
                 instruction_type: InstructionType::PHA,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x68 => Ok(Instruction {
+            0x68 => Some(Instruction {
                 instruction_type: InstructionType::PLA,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x08 => Ok(Instruction {
+            0x08 => Some(Instruction {
                 instruction_type: InstructionType::PHP,
                 addressing_mode: AddressingMode::Implied,
             }),
-            0x28 => Ok(Instruction {
+            0x28 => Some(Instruction {
                 instruction_type: InstructionType::PLP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
             //STX
-            0x86 => Ok(Instruction {
+            0x86 => Some(Instruction {
                 instruction_type: InstructionType::STX,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x96 => Ok(Instruction {
+            0x96 => Some(Instruction {
                 instruction_type: InstructionType::STX,
                 addressing_mode: AddressingMode::ZeroPageY,
             }),
-            0x8E => Ok(Instruction {
+            0x8E => Some(Instruction {
                 instruction_type: InstructionType::STX,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //STY
-            0x84 => Ok(Instruction {
+            0x84 => Some(Instruction {
                 instruction_type: InstructionType::STY,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
-            0x94 => Ok(Instruction {
+            0x94 => Some(Instruction {
                 instruction_type: InstructionType::STY,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
-            0x8C => Ok(Instruction {
+            0x8C => Some(Instruction {
                 instruction_type: InstructionType::STY,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
             //ILLEGAL INSTUCTIONS
-            0x80 => Ok(Instruction {
+            0x80 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x02 => Ok(Instruction {
+            0x02 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x12 => Ok(Instruction {
+            0x12 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x22 => Ok(Instruction {
+            0x22 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x32 => Ok(Instruction {
+            0x32 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x42 => Ok(Instruction {
+            0x42 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x52 => Ok(Instruction {
+            0x52 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x62 => Ok(Instruction {
+            0x62 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x72 => Ok(Instruction {
+            0x72 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x82 => Ok(Instruction {
+            0x82 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x92 => Ok(Instruction {
+            0x92 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0xB2 => Ok(Instruction {
+            0xB2 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xC2 => Ok(Instruction {
+            0xC2 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xD2 => Ok(Instruction {
+            0xD2 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xE2 => Ok(Instruction {
+            0xE2 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xF2 => Ok(Instruction {
+            0xF2 => Some(Instruction {
                 instruction_type: InstructionType::JAM,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x03 => Ok(Instruction {
+            0x03 => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0x13 => Ok(Instruction {
+            0x13 => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0x23 => Ok(Instruction {
+            0x23 => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0x33 => Ok(Instruction {
+            0x33 => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0x43 => Ok(Instruction {
+            0x43 => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0x53 => Ok(Instruction {
+            0x53 => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0x63 => Ok(Instruction {
+            0x63 => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0x73 => Ok(Instruction {
+            0x73 => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0x83 => Ok(Instruction {
+            0x83 => Some(Instruction {
                 instruction_type: InstructionType::SAX,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0x93 => Ok(Instruction {
+            0x93 => Some(Instruction {
                 instruction_type: InstructionType::SHA,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0xA3 => Ok(Instruction {
+            0xA3 => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0xB3 => Ok(Instruction {
+            0xB3 => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0xC3 => Ok(Instruction {
+            0xC3 => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0xD3 => Ok(Instruction {
+            0xD3 => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0xE3 => Ok(Instruction {
+            0xE3 => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::IndirectX,
             }),
 
-            0xF3 => Ok(Instruction {
+            0xF3 => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::IndirectY,
             }),
 
-            0x04 => Ok(Instruction {
+            0x04 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x14 => Ok(Instruction {
+            0x14 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x34 => Ok(Instruction {
+            0x34 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x44 => Ok(Instruction {
+            0x44 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x54 => Ok(Instruction {
+            0x54 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x64 => Ok(Instruction {
+            0x64 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x74 => Ok(Instruction {
+            0x74 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0xD4 => Ok(Instruction {
+            0xD4 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0xF4 => Ok(Instruction {
+            0xF4 => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x07 => Ok(Instruction {
+            0x07 => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x17 => Ok(Instruction {
+            0x17 => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x27 => Ok(Instruction {
+            0x27 => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x37 => Ok(Instruction {
+            0x37 => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x47 => Ok(Instruction {
+            0x47 => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x57 => Ok(Instruction {
+            0x57 => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x67 => Ok(Instruction {
+            0x67 => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x77 => Ok(Instruction {
+            0x77 => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x87 => Ok(Instruction {
+            0x87 => Some(Instruction {
                 instruction_type: InstructionType::SAX,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0x97 => Ok(Instruction {
+            0x97 => Some(Instruction {
                 instruction_type: InstructionType::SAX,
                 addressing_mode: AddressingMode::ZeroPageY,
             }),
 
-            0xA7 => Ok(Instruction {
+            0xA7 => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0xB7 => Ok(Instruction {
+            0xB7 => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::ZeroPageY,
             }),
 
-            0xC7 => Ok(Instruction {
+            0xC7 => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0xD7 => Ok(Instruction {
+            0xD7 => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0xE7 => Ok(Instruction {
+            0xE7 => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::ZeroPage,
             }),
 
-            0xF7 => Ok(Instruction {
+            0xF7 => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::ZeroPageX,
             }),
 
-            0x1A => Ok(Instruction {
+            0x1A => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x3A => Ok(Instruction {
+            0x3A => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x5A => Ok(Instruction {
+            0x5A => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x7A => Ok(Instruction {
+            0x7A => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xDA => Ok(Instruction {
+            0xDA => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0xFA => Ok(Instruction {
+            0xFA => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Implied,
             }),
 
-            0x0B => Ok(Instruction {
+            0x0B => Some(Instruction {
                 instruction_type: InstructionType::ANC,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x1B => Ok(Instruction {
+            0x1B => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x2B => Ok(Instruction {
+            0x2B => Some(Instruction {
                 instruction_type: InstructionType::ANC,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x3B => Ok(Instruction {
+            0x3B => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x4B => Ok(Instruction {
+            0x4B => Some(Instruction {
                 instruction_type: InstructionType::ALR,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x5B => Ok(Instruction {
+            0x5B => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x6B => Ok(Instruction {
+            0x6B => Some(Instruction {
                 instruction_type: InstructionType::ARR,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x7B => Ok(Instruction {
+            0x7B => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x8B => Ok(Instruction {
+            0x8B => Some(Instruction {
                 instruction_type: InstructionType::ANE,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0x9B => Ok(Instruction {
+            0x9B => Some(Instruction {
                 instruction_type: InstructionType::TAS,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0xAB => Ok(Instruction {
+            0xAB => Some(Instruction {
                 instruction_type: InstructionType::LXA,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0xBB => Ok(Instruction {
+            0xBB => Some(Instruction {
                 instruction_type: InstructionType::LAS,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0xCB => Ok(Instruction {
+            0xCB => Some(Instruction {
                 instruction_type: InstructionType::SBX,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0xDB => Ok(Instruction {
+            0xDB => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0xEB => Ok(Instruction {
+            0xEB => Some(Instruction {
                 instruction_type: InstructionType::USBC,
                 addressing_mode: AddressingMode::Immediate,
             }),
 
-            0xFB => Ok(Instruction {
+            0xFB => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x0C => Ok(Instruction {
+            0x0C => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x1C => Ok(Instruction {
+            0x1C => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x3C => Ok(Instruction {
+            0x3C => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x5C => Ok(Instruction {
+            0x5C => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x7C => Ok(Instruction {
+            0x7C => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x9C => Ok(Instruction {
+            0x9C => Some(Instruction {
                 instruction_type: InstructionType::SHY,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0xDC => Ok(Instruction {
+            0xDC => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0xFC => Ok(Instruction {
+            0xFC => Some(Instruction {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x9E => Ok(Instruction {
+            0x9E => Some(Instruction {
                 instruction_type: InstructionType::SHX,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0x0F => Ok(Instruction {
+            0x0F => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x1F => Ok(Instruction {
+            0x1F => Some(Instruction {
                 instruction_type: InstructionType::SLO,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x2F => Ok(Instruction {
+            0x2F => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x3F => Ok(Instruction {
+            0x3F => Some(Instruction {
                 instruction_type: InstructionType::RLA,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x4F => Ok(Instruction {
+            0x4F => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x5F => Ok(Instruction {
+            0x5F => Some(Instruction {
                 instruction_type: InstructionType::SRE,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x6F => Ok(Instruction {
+            0x6F => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x7F => Ok(Instruction {
+            0x7F => Some(Instruction {
                 instruction_type: InstructionType::RRA,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0x8F => Ok(Instruction {
+            0x8F => Some(Instruction {
                 instruction_type: InstructionType::SAX,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0x9F => Ok(Instruction {
+            0x9F => Some(Instruction {
                 instruction_type: InstructionType::SHA,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0xAF => Ok(Instruction {
+            0xAF => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0xBF => Ok(Instruction {
+            0xBF => Some(Instruction {
                 instruction_type: InstructionType::LAX,
                 addressing_mode: AddressingMode::AbsoluteY,
             }),
 
-            0xCF => Ok(Instruction {
+            0xCF => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0xDF => Ok(Instruction {
+            0xDF => Some(Instruction {
                 instruction_type: InstructionType::DCP,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
-            0xEF => Ok(Instruction {
+            0xEF => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::Absolute,
             }),
 
-            0xFF => Ok(Instruction {
+            0xFF => Some(Instruction {
                 instruction_type: InstructionType::ISC,
                 addressing_mode: AddressingMode::AbsoluteX,
             }),
 
             //UNKNOWN INSTRUCTION
-            _ => {
-                eprintln!("Unknown opcode: {:#X}", opcode);
-                Ok(Instruction {
-                    instruction_type: InstructionType::NOP,
-                    addressing_mode: AddressingMode::Implied,
-                })
-            }
+            _ => None,
         }
     }
 
@@ -1571,6 +1577,22 @@ impl Instruction {
             .set_bit(StatusRegisterBit::Negative, value & (1 << 7) > 0);
     }
 
+    // Writes the result of a read-modify-write instruction
+    //
+    // The 6502 writes the unmodified value back while it modifies it, and the result on the next
+    // cycle. Hardware like the MMC1 reacts to that extra write.
+    fn write_modified(
+        cpu: &mut Cpu,
+        address: u16,
+        value: u8,
+        result: u8,
+        ppu: &mut Ppu,
+    ) -> Result<(), MainError> {
+        cpu.memory.write(address, value, ppu)?;
+        cpu.memory.write(address, result, ppu)?;
+        Ok(())
+    }
+
     pub fn execute(&self, cpu: &mut Cpu, ppu: &mut Ppu) -> Result<(), MainError> {
         let is_write_only = self.is_write_only();
         let operand_value = cpu.get_operand_value(&self.addressing_mode, ppu, is_write_only)?;
@@ -1710,7 +1732,7 @@ impl Instruction {
                 let address = operand_value.address.expect("INC Address is None");
                 let value = operand_value.value.expect("INC value is None");
                 let new_value = value.wrapping_add(1);
-                cpu.memory.write(address, new_value, ppu)?;
+                Self::write_modified(cpu, address, value, new_value, ppu)?;
                 Self::set_status_if_zero(new_value, cpu);
                 Self::set_status_if_negative(new_value, cpu);
                 Ok(())
@@ -1735,7 +1757,7 @@ impl Instruction {
                 let address = operand_value.address.expect("DEC Address is None");
                 let value = operand_value.value.expect("DEC value is None");
                 let new_value = value.wrapping_sub(1);
-                cpu.memory.write(address, new_value, ppu)?;
+                Self::write_modified(cpu, address, value, new_value, ppu)?;
                 Self::set_status_if_zero(new_value, cpu);
                 Self::set_status_if_negative(new_value, cpu);
                 Ok(())
@@ -1759,7 +1781,7 @@ impl Instruction {
                 let address = operand_value.address.expect("DCP Address is None");
                 let value = operand_value.value.expect("DCP value is None");
                 let new_value = value.wrapping_sub(1);
-                cpu.memory.write(address, new_value, ppu)?;
+                Self::write_modified(cpu, address, value, new_value, ppu)?;
 
                 let reg = cpu.accumulator.get();
                 cpu.status_register
@@ -1784,7 +1806,7 @@ impl Instruction {
                         .set_bit(StatusRegisterBit::Carry, operator_value & (1 << 0) != 0);
                     Self::set_status_if_zero(result, cpu);
                     Self::set_status_if_negative(result, cpu);
-                    cpu.memory.write(address, result, ppu)?;
+                    Self::write_modified(cpu, address, operator_value, result, ppu)?;
                     op_value = result;
                 }
 
@@ -1815,7 +1837,7 @@ impl Instruction {
                 let carry = u8::from(cpu.status_register.get_carry());
                 if self.instruction_type == InstructionType::ISC {
                     let address = operand_value.address.expect("ISC Address is None");
-                    cpu.memory.write(address, op_value.wrapping_add(1), ppu)?;
+                    Self::write_modified(cpu, address, op_value, op_value.wrapping_add(1), ppu)?;
                     result = acc.wrapping_sub(op_value).wrapping_sub(2 - carry);
                     // use the carry condition of ADC
                     did_carry = result < acc || (result == 0 && carry == 1) || op_value == 0xff;
@@ -1909,7 +1931,7 @@ impl Instruction {
                     .set_bit(StatusRegisterBit::Carry, operator_value & (1 << 7) != 0);
                 Self::set_status_if_zero(result, cpu);
 
-                cpu.memory.write(address, result, ppu)?;
+                Self::write_modified(cpu, address, operator_value, result, ppu)?;
                 cpu.accumulator.set(cpu.accumulator.get() | result);
                 Self::set_status_if_negative(cpu.accumulator.get(), cpu);
                 Ok(())
@@ -1924,7 +1946,7 @@ impl Instruction {
                 Self::set_status_if_negative(result, cpu);
 
                 if let Some(address) = operand_value.address {
-                    cpu.memory.write(address, result, ppu)?;
+                    Self::write_modified(cpu, address, operator_value, result, ppu)?;
                 } else {
                     cpu.accumulator.set(result)
                 }
@@ -1940,7 +1962,7 @@ impl Instruction {
                 Self::set_status_if_negative(result, cpu);
 
                 if let Some(address) = operand_value.address {
-                    cpu.memory.write(address, result, ppu)?;
+                    Self::write_modified(cpu, address, operator_value, result, ppu)?;
                 } else {
                     cpu.accumulator.set(result)
                 }
@@ -1955,7 +1977,7 @@ impl Instruction {
                     .set_bit(StatusRegisterBit::Carry, operator_value & 1 != 0);
                 Self::set_status_if_zero(result, cpu);
                 Self::set_status_if_negative(result, cpu);
-                cpu.memory.write(address, result, ppu)?;
+                Self::write_modified(cpu, address, operator_value, result, ppu)?;
 
                 let value = cpu.accumulator.get() ^ result;
                 cpu.accumulator.set(value);
@@ -1974,7 +1996,7 @@ impl Instruction {
                 Self::set_status_if_negative(result, cpu);
 
                 if let Some(address) = operand_value.address {
-                    cpu.memory.write(address, result, ppu)?;
+                    Self::write_modified(cpu, address, operator_value, result, ppu)?;
                 } else {
                     cpu.accumulator.set(result)
                 }
@@ -1991,7 +2013,7 @@ impl Instruction {
                 Self::set_status_if_negative(result, cpu);
 
                 if let Some(address) = operand_value.address {
-                    cpu.memory.write(address, result, ppu)?;
+                    Self::write_modified(cpu, address, operator_value, result, ppu)?;
                 } else {
                     cpu.accumulator.set(result)
                 }
@@ -2008,7 +2030,7 @@ impl Instruction {
                 Self::set_status_if_zero(result, cpu);
                 Self::set_status_if_negative(result, cpu);
 
-                cpu.memory.write(address, result, ppu)?;
+                Self::write_modified(cpu, address, operator_value, result, ppu)?;
 
                 let value = cpu.accumulator.get() & result;
                 cpu.accumulator.set(value);
@@ -2302,6 +2324,41 @@ impl Instruction {
         )
     }
 
+    // Return true if the instruction writes to memory on the cycle that has `cycles_left` cycles
+    // of the instruction following it
+    pub fn is_write_cycle(&self, cycles_left: u8) -> bool {
+        match self.instruction_type {
+            InstructionType::STA
+            | InstructionType::STX
+            | InstructionType::STY
+            | InstructionType::SAX
+            | InstructionType::SHA
+            | InstructionType::SHX
+            | InstructionType::SHY
+            | InstructionType::TAS
+            | InstructionType::PHA
+            | InstructionType::PHP => cycles_left == 0,
+            // read-modify-write instructions write the unmodified value back before the result
+            InstructionType::ASL
+            | InstructionType::LSR
+            | InstructionType::ROL
+            | InstructionType::ROR
+            | InstructionType::INC
+            | InstructionType::DEC
+            | InstructionType::DCP
+            | InstructionType::ISC
+            | InstructionType::SLO
+            | InstructionType::RLA
+            | InstructionType::SRE
+            | InstructionType::RRA => {
+                !matches!(self.addressing_mode, AddressingMode::Accumulator) && cycles_left <= 1
+            }
+            InstructionType::JSR => (1..=2).contains(&cycles_left),
+            InstructionType::BRK => (2..=4).contains(&cycles_left),
+            _ => false,
+        }
+    }
+
     fn is_write_only(&self) -> bool {
         matches!(
             self.instruction_type,
@@ -2320,11 +2377,11 @@ impl Instruction {
         let mut out_val: String = "None".to_string();
         let mut out_addr: String = "None".to_string();
 
-        if operand_value.value.is_some() {
-            out_val = format!("0x{:02X}", operand_value.value.unwrap());
+        if let Some(value) = operand_value.value {
+            out_val = format!("0x{:02X}", value);
         }
-        if operand_value.address.is_some() {
-            out_addr = format!("0x{:04X}", operand_value.address.unwrap());
+        if let Some(address) = operand_value.address {
+            out_addr = format!("0x{:04X}", address);
         }
 
         log::debug!(
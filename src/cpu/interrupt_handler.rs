@@ -7,6 +7,43 @@ pub(crate) enum InterruptState {
     Uninitialized,
     Booting,
 }
+
+impl InterruptState {
+    // The states in the order they are numbered in save states, which is `state as u8`
+    const ALL: [InterruptState; 5] = [
+        InterruptState::NormalOperation,
+        InterruptState::IRQ,
+        InterruptState::NMI,
+        InterruptState::Uninitialized,
+        InterruptState::Booting,
+    ];
+
+    pub(crate) fn from_number(number: u8) -> Option<Self> {
+        Self::ALL.get(number as usize).copied()
+    }
+}
+
+// The chips that can pull the shared IRQ line of the CPU low
+//
+// The line is level sensitive, a source keeps asserting it until the interrupt is acknowledged
+// at the source itself, for example by reading $4015 for the APU frame counter.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    Mapper,
+}
+
+impl IrqSource {
+    // The bit this source occupies in the IRQ line of the CPU
+    pub(crate) fn mask(self) -> u8 {
+        match self {
+            IrqSource::FrameCounter => 0b001,
+            IrqSource::Dmc => 0b010,
+            IrqSource::Mapper => 0b100,
+        }
+    }
+}
 //
 //
 //
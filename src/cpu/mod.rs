@@ -1,17 +1,32 @@
+use crate::audio::AudioOutput;
+use crate::cpu::disassembler::{disassemble_instruction, Line};
 use crate::cpu::instructions::{AddressingMode, Instruction, InstructionType};
-use crate::error::{MemoryError, MyGetCpuError, MyTickError};
+use crate::error::{MainError, MemoryError, MyGetCpuError, MyTickError};
+use crate::error::{MovieError, SaveStateError};
+use crate::memory::input::{Controller, InputDevice, Port, ScriptedInput, Unplugged};
 use crate::memory::Memory;
-use crate::MainError;
-use debug::DebugMode;
+use crate::movie::{Movie, MovieRecorder, COMMAND_POWER, COMMAND_RESET};
+use crate::rewind::{RewindBuffer, FRAMES_PER_SECOND};
+use crate::savestate::{read_header, write_header, StateCommand, StateReader, StateWriter};
+pub use debug::Debugger;
+use debug::{DebugMode, Trace};
 use interrupt_handler::InterruptState;
+pub use interrupt_handler::IrqSource;
 use log::warn;
 use registers::{CpuRegister, ProgramCounter, StatusRegister, StatusRegisterBit};
-use tudelft_nes_ppu::{Cpu as CpuTemplate, Ppu};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use tudelft_nes_ppu::{Cpu as CpuTemplate, Mirroring, Ppu};
 use tudelft_nes_test::TestableCpu;
 pub(crate) mod debug;
+pub mod disassembler;
 mod instructions;
 mod interrupt_handler;
 mod registers;
+mod watchpoints;
 
 struct OperandValue {
     value: Option<u8>,
@@ -27,6 +42,7 @@ pub struct Cpu {
     program_counter: ProgramCounter,
     status_register: StatusRegister,
     current_instruction: Instruction,
+    current_opcode: u8, // Opcode of the current instruction, which save states keep
     current_cycle: u8,
     instruction_cycle_count: u8,
     interrupt_polling_cycle: u8,
@@ -34,13 +50,20 @@ pub struct Cpu {
     nmi_line_prev: bool,
     nmi_line_current: bool,
     nmi_line_triggered: bool,
-    irq_line_triggered: bool,
+    irq_line: u8, // One bit for every IrqSource currently asserting the line
+    interrupt_disable_at_poll: bool, // The I flag as seen by the next interrupt poll
+    dma_stall_cycles: u8, // Cycles the CPU stays halted for a DMC sample fetch
     branch_success: bool,
     page_crossing: bool,
     memory: Memory,
     total_cycles: u64,
     instructions_executed: u64,
     debug: DebugMode,
+    state_commands: Option<Receiver<StateCommand>>, // Save states to take or load
+    state_frame: u64, // Frame in which the state commands were last checked
+    rewind: Option<RewindBuffer>, // Snapshots to go back to
+    debugger: Option<Debugger>, // Stops execution for interactive debugging
+    trace: Option<Trace>, // Output of the instruction trace in `DebugMode::Emu`
 }
 
 /// Trait for making the CPU testable in automated tests
@@ -59,6 +82,7 @@ impl TestableCpu for Cpu {
                 instruction_type: InstructionType::NOP,
                 addressing_mode: AddressingMode::Absolute,
             },
+            current_opcode: 0x0C,
             current_cycle: 1,
             instruction_cycle_count: 0,
             interrupt_polling_cycle: 0,
@@ -66,12 +90,19 @@ impl TestableCpu for Cpu {
             nmi_line_prev: false,
             nmi_line_current: false,
             nmi_line_triggered: false,
-            irq_line_triggered: false,
+            irq_line: 0,
+            interrupt_disable_at_poll: true,
+            dma_stall_cycles: 0,
             branch_success: false,
             page_crossing: false,
             total_cycles: 0,
             instructions_executed: 0,
             debug: DebugMode::No,
+            state_commands: None,
+            state_frame: 0,
+            rewind: None,
+            debugger: None,
+            trace: None,
             memory: Memory::new(_rom)?,
         })
     }
@@ -104,18 +135,20 @@ impl CpuTemplate for Cpu {
     // for some games to work properly. That means that it won’t work to execute an entire instruction
     // every time tick is called. It should take multiple calls to tick to execute one instruction.
     fn tick(&mut self, ppu: &mut Ppu) -> Result<(), MyTickError> {
-        // set the cpu to the startup state fi
-        if self.current_cycle == self.interrupt_polling_cycle {
-            // this line is for interrupt hijacking to be working later
-            let current_interrupt = self.poll_interrupts();
-            if current_interrupt == InterruptState::IRQ
-                && self.status_register.get_bit(StatusRegisterBit::Interrupt)
-            {
-                self.interrupt_state = InterruptState::NormalOperation;
-            } else {
-                self.interrupt_state = current_interrupt;
-            }
+        // states are saved and loaded between two cycles, when a frame has started
+        if self.memory.frame() != self.state_frame {
+            self.take_snapshot();
+            self.run_state_commands();
+            self.state_frame = self.memory.frame();
         }
+        self.memory.restore_ppu(ppu);
+
+        // the CPU is halted while the DMC fetches a sample, so the instruction does not progress
+        if self.dma_stall_cycles > 0 {
+            self.dma_stall_cycles -= 1;
+            return self.end_cycle(ppu);
+        }
+
         // execute interrupt or opcode
         if self.current_cycle > self.instruction_cycle_count {
             self.current_cycle = 1;
@@ -149,17 +182,41 @@ impl CpuTemplate for Cpu {
                     //     .set_bit(StatusRegisterBit::InterruptBit, true);
                 }
                 InterruptState::IRQ => {
-                    warn!("Add interface for IRQ")
+                    log::debug!("Executing IRQ");
+                    self.push_pc_and_status_on_stack(ppu)?;
+                    let irq_lobyte = self.memory.read(0xFFFE, self, ppu)?;
+                    let irq_hibyte = self.memory.read(0xFFFF, self, ppu)?;
+                    self.program_counter.set_lobyte(irq_lobyte);
+                    self.program_counter.set_hibyte(irq_hibyte);
+                    // keep the still asserted line from interrupting the handler right away
+                    self.status_register
+                        .set_bit(StatusRegisterBit::Interrupt, true);
+
+                    self.instruction_cycle_count = 7;
+                    self.interrupt_state = InterruptState::NormalOperation;
+                    self.interrupt_polling_cycle = 0;
                 }
                 InterruptState::NormalOperation => {
+                    self.run_debugger(ppu);
                     log::debug!("\n\n---------------");
-                    self.debug(self.memory.read(self.program_counter.get(), self, ppu)?);
+                    self.debug();
                     let opcode = self.read_next_value(ppu)?;
                     log::debug!("Opcode: {:02X}", opcode);
                     let instruction: Instruction =
                         Instruction::decode(opcode).expect("Failed decoding opcode");
+                    let interrupt_disable =
+                        self.status_register.get_bit(StatusRegisterBit::Interrupt);
                     instruction.execute(self, ppu)?;
 
+                    // CLI, SEI and PLP change the I flag after the interrupts have been polled,
+                    // so an IRQ is only taken (or blocked) after the next instruction
+                    self.interrupt_disable_at_poll = match instruction.instruction_type {
+                        InstructionType::CLI | InstructionType::SEI | InstructionType::PLP => {
+                            interrupt_disable
+                        }
+                        _ => self.status_register.get_bit(StatusRegisterBit::Interrupt),
+                    };
+
                     self.instruction_cycle_count = Instruction::get_instruction_duration(opcode)?;
                     log::debug!(
                         "Instruction cycle count set to {}",
@@ -181,6 +238,7 @@ impl CpuTemplate for Cpu {
 
                     self.page_crossing = false;
                     self.current_instruction = instruction;
+                    self.current_opcode = opcode;
                     self.instructions_executed += 1;
                     self.print_cpu_state_header();
                 }
@@ -203,31 +261,35 @@ impl CpuTemplate for Cpu {
                     self.interrupt_state = InterruptState::NormalOperation;
                     self.interrupt_polling_cycle = 0;
                 }
-                InterruptState::IRQ => {
-                    if !self.status_register.get_bit(StatusRegisterBit::Interrupt) {
-                        let nmi_lobyte = self.memory.read(0xFFFE, self, ppu)?;
-                        let nmi_hibyte = self.memory.read(0xFFFF, self, ppu)?;
-                        self.program_counter.set_lobyte(nmi_lobyte);
-                        self.program_counter.set_hibyte(nmi_hibyte);
+                InterruptState::IRQ
+                    if !self.status_register.get_bit(StatusRegisterBit::Interrupt) =>
+                {
+                    let nmi_lobyte = self.memory.read(0xFFFE, self, ppu)?;
+                    let nmi_hibyte = self.memory.read(0xFFFF, self, ppu)?;
+                    self.program_counter.set_lobyte(nmi_lobyte);
+                    self.program_counter.set_hibyte(nmi_hibyte);
 
-                        self.instruction_cycle_count = 7;
-                        self.interrupt_state = InterruptState::NormalOperation;
-                        self.interrupt_polling_cycle = 0;
-                    }
+                    self.instruction_cycle_count = 7;
+                    self.interrupt_state = InterruptState::NormalOperation;
+                    self.interrupt_polling_cycle = 0;
                 }
                 _ => (),
             }
         }
 
-        if self.nmi_line_current && !self.nmi_line_prev {
-            self.nmi_line_triggered = true;
+        // poll the interrupt lines at the end of the second to last cycle of an instruction
+        if self.current_cycle == self.interrupt_polling_cycle {
+            // this line is for interrupt hijacking to be working later
+            let current_interrupt = self.poll_interrupts();
+            if current_interrupt == InterruptState::IRQ && self.interrupt_disable_at_poll {
+                self.interrupt_state = InterruptState::NormalOperation;
+            } else {
+                self.interrupt_state = current_interrupt;
+            }
         }
-        self.print_cpu_state();
-        self.current_cycle += 1;
-        self.total_cycles += 1;
-        self.nmi_line_prev = self.nmi_line_current;
-        self.nmi_line_current = false;
 
+        self.end_cycle(ppu)?;
+        self.current_cycle += 1;
         Ok(())
     }
 
@@ -248,39 +310,87 @@ impl CpuTemplate for Cpu {
 }
 
 impl Cpu {
-    // Get instruction length of an addressing mode
-    fn addressing_mode_get_bytes(&self, addressing_mode: &AddressingMode) -> Vec<u8> {
-        let length = addressing_mode.length() as u16;
-        (0..length)
-            .map(|n| self.memory_read(self.program_counter.get() + n))
-            .collect::<Vec<_>>()
+    // Finish a CPU cycle, this also happens for cycles in which the CPU is stalled
+    //
+    // Clocks the APU and the mapper, performs DMC sample fetches and samples the interrupt lines.
+    fn end_cycle(&mut self, ppu: &mut Ppu) -> Result<(), MyTickError> {
+        if self.nmi_line_current && !self.nmi_line_prev {
+            self.nmi_line_triggered = true;
+        }
+        self.print_cpu_state();
+        self.memory.tick_apu();
+        if self.memory.dmc_dma(self, ppu)? {
+            // the DMA waits for the CPU to finish a write before halting it
+            let cycles_left = self
+                .instruction_cycle_count
+                .saturating_sub(self.current_cycle);
+            self.dma_stall_cycles += if self.current_instruction.is_write_cycle(cycles_left) {
+                3
+            } else {
+                4
+            };
+        }
+        self.set_irq_line(IrqSource::FrameCounter, self.memory.frame_counter_irq());
+        self.set_irq_line(IrqSource::Dmc, self.memory.dmc_irq());
+        self.memory.tick_ppu_timing();
+        let command = self.memory.take_command();
+        if command & COMMAND_POWER != 0 {
+            self.power_on();
+        } else if command & COMMAND_RESET != 0 {
+            self.memory.reset();
+            self.restart();
+        }
+        self.memory.tick_mapper();
+        self.memory.tick_battery();
+        self.set_irq_line(IrqSource::Mapper, self.memory.mapper_irq());
+        self.total_cycles += 1;
+        self.nmi_line_prev = self.nmi_line_current;
+        self.nmi_line_current = false;
+        Ok(())
+    }
+
+    // Disassembles the instruction at the program counter, without reading through the bus
+    fn current_instruction(&self) -> Line {
+        let program_counter = self.program_counter.get();
+        let code: Vec<u8> = (0..3)
+            .map(|offset| {
+                let address = program_counter.wrapping_add(offset);
+                self.memory.read_cpu_mem(address).unwrap_or(0)
+            })
+            .collect();
+        disassemble_instruction(&code, program_counter)
     }
 
     // Print debug information in a format that can be compared with other
     // emulators
-    fn debug(&self, opcode: u8) {
-        if self.debug == DebugMode::Emu {
-            if let Ok(instruction) = Instruction::decode(opcode) {
-                let raw_bytes = self.addressing_mode_get_bytes(&instruction.addressing_mode);
-                let bytes = raw_bytes
-                    .iter()
-                    .map(|arg| format!("{:02X}", arg))
-                    .collect::<Vec<_>>()
-                    .join(" ");
-
-                println!(
-                    "{:04X}  {:8}  {:32?} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
-                    self.program_counter.get(),
-                    bytes,
-                    instruction.instruction_type,
-                    self.accumulator.get(),
-                    self.x_register.get(),
-                    self.y_register.get(),
-                    self.status_register.get() & !(1 << 4),
-                    self.stack_pointer.get(),
-                    self.total_cycles,
-                );
-            }
+    //
+    // The lines have the format of expected-output/nestest.log, which only shows the mnemonic of
+    // the instruction and not its operand.
+    fn debug(&mut self) {
+        if self.debug != DebugMode::Emu {
+            return;
+        }
+        let line = self.current_instruction();
+        let Some(Trace(output)) = &mut self.trace else {
+            return;
+        };
+        let result = writeln!(
+            output,
+            "{:04X}  {:8}  {} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            line.address,
+            line.hex_bytes(),
+            line.mnemonic,
+            self.accumulator.get(),
+            self.x_register.get(),
+            self.y_register.get(),
+            self.status_register.get() & !(1 << 4),
+            self.stack_pointer.get(),
+            self.total_cycles,
+        );
+        if let Err(e) = result {
+            log::error!("stopped the instruction trace: {}", e);
+            self.trace = None;
+            self.debug = DebugMode::No;
         }
     }
 
@@ -505,7 +615,290 @@ impl Cpu {
         self.nmi_line_current = true;
     }
 
+    // Returns the mirroring the PPU has to be created with for the cartridge
+    pub fn ppu_mirroring(&self) -> Mirroring {
+        self.memory.ppu_mirroring()
+    }
+
+    // Keep battery-backed PRG RAM in a save file, this does nothing for cartridges without a battery
+    //
+    // The file is written about every second and when the CPU is dropped, which `run_cpu` never
+    // does, so a windowed session loses changes from the last second before the window closes.
+    pub fn set_battery_file(&mut self, path: PathBuf) -> io::Result<()> {
+        self.memory.set_battery_file(path)
+    }
+
+    // Route the output of the APU to an audio sink, without one no audio is produced
+    pub fn set_audio_output(&mut self, output: AudioOutput) {
+        self.memory.set_audio_output(output);
+    }
+
+    // Plug a device into a controller port, by default the first port has a pad that is read
+    // from the window of the PPU and the second port is empty
+    pub fn set_input_device(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        self.memory.set_input_device(port, device);
+    }
+
+    // Returns the MD5 of the PRG and CHR ROM of the cartridge
+    pub fn rom_checksum(&self) -> [u8; 16] {
+        self.memory.rom_checksum()
+    }
+
+    // Press the reset button, the CPU starts again at the reset vector with its registers intact
+    //
+    // Like a power cycle the reset happens when the next frame starts, which is where movies
+    // record it.
+    pub fn reset(&mut self) {
+        self.memory.queue_command(COMMAND_RESET);
+    }
+
+    // Switch the console off and on again when the next frame starts
+    pub fn power_cycle(&mut self) {
+        self.memory.queue_command(COMMAND_POWER);
+    }
+
+    fn power_on(&mut self) {
+        self.memory.power_cycle();
+        self.accumulator = CpuRegister::default();
+        self.x_register = CpuRegister::default();
+        self.y_register = CpuRegister::default();
+        self.stack_pointer = CpuRegister::default();
+        self.status_register = StatusRegister::default();
+        self.restart();
+    }
+
+    // Abandons the current instruction and runs the reset sequence on the next cycle
+    fn restart(&mut self) {
+        self.interrupt_state = InterruptState::Uninitialized;
+        self.current_cycle = 1;
+        self.instruction_cycle_count = 0;
+        self.dma_stall_cycles = 0;
+        self.nmi_line_current = false;
+        self.nmi_line_triggered = false;
+    }
+
+    // Record the input and resets from now on into a movie, this has to start at power-on
+    pub fn record_movie(&mut self, recorder: MovieRecorder) {
+        self.memory.record_movie(recorder);
+    }
+
+    // Play a movie from power-on, which replaces the devices in both ports with its pads
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<(), MovieError> {
+        if movie.rom_checksum != self.rom_checksum() {
+            return Err(MovieError::ChecksumMismatch);
+        }
+        let script = Arc::new(movie.input_script());
+        for (port, plugged) in [Port::One, Port::Two].into_iter().zip(movie.ports) {
+            let device: Box<dyn InputDevice> = if plugged {
+                let source = ScriptedInput::new(script.clone(), port);
+                Box::new(Controller::new(Box::new(source)))
+            } else {
+                Box::new(Unplugged)
+            };
+            self.memory.set_input_device(port, device);
+        }
+        self.memory.play_movie_commands(movie.commands());
+        Ok(())
+    }
+
+    // Save the state of the CPU, the memory and the cartridge, see `Memory::save_state` for what is
+    // left out
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        write_header(&mut state, self.rom_checksum());
+        state.write_u8(self.accumulator.get());
+        state.write_u8(self.x_register.get());
+        state.write_u8(self.y_register.get());
+        state.write_u8(self.stack_pointer.get());
+        state.write_u16(self.program_counter.get());
+        state.write_u8(self.status_register.get());
+        state.write_u8(self.current_opcode);
+        state.write_u8(self.current_cycle);
+        state.write_u8(self.instruction_cycle_count);
+        state.write_u8(self.interrupt_polling_cycle);
+        state.write_u8(self.interrupt_state as u8);
+        state.write_bool(self.nmi_line_prev);
+        state.write_bool(self.nmi_line_current);
+        state.write_bool(self.nmi_line_triggered);
+        state.write_u8(self.irq_line);
+        state.write_bool(self.interrupt_disable_at_poll);
+        state.write_u8(self.dma_stall_cycles);
+        state.write_bool(self.branch_success);
+        state.write_bool(self.page_crossing);
+        state.write_u64(self.total_cycles);
+        state.write_u64(self.instructions_executed);
+        self.memory.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    // Load a state saved with the same ROM, the emulator is left as it was when this fails
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut state = StateReader::new(data);
+        read_header(&mut state, self.rom_checksum())?;
+        let backup = self.save_state();
+        if let Err(e) = self.read_state(&mut state) {
+            let mut state = StateReader::new(&backup);
+            read_header(&mut state, self.rom_checksum())
+                .and_then(|_| self.read_state(&mut state))
+                .expect("the state was saved just now");
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.accumulator.set(state.read_u8()?);
+        self.x_register.set(state.read_u8()?);
+        self.y_register.set(state.read_u8()?);
+        self.stack_pointer.set(state.read_u8()?);
+        self.program_counter.set(state.read_u16()?);
+        self.status_register.set_from_stack(state.read_u8()?);
+        self.current_opcode = state.read_u8()?;
+        self.current_instruction = Instruction::decode(self.current_opcode).map_err(|_| {
+            SaveStateError::Mismatch(format!("unknown opcode {:02X}", self.current_opcode))
+        })?;
+        self.current_cycle = state.read_u8()?;
+        self.instruction_cycle_count = state.read_u8()?;
+        self.interrupt_polling_cycle = state.read_u8()?;
+        let interrupt_state = state.read_u8()?;
+        self.interrupt_state = InterruptState::from_number(interrupt_state).ok_or_else(|| {
+            SaveStateError::Mismatch(format!("unknown interrupt state {}", interrupt_state))
+        })?;
+        self.nmi_line_prev = state.read_bool()?;
+        self.nmi_line_current = state.read_bool()?;
+        self.nmi_line_triggered = state.read_bool()?;
+        self.irq_line = state.read_u8()?;
+        self.interrupt_disable_at_poll = state.read_bool()?;
+        self.dma_stall_cycles = state.read_u8()?;
+        self.branch_success = state.read_bool()?;
+        self.page_crossing = state.read_bool()?;
+        self.total_cycles = state.read_u64()?;
+        self.instructions_executed = state.read_u64()?;
+        self.memory.load_state(state)?;
+        if !state.is_empty() {
+            return Err(SaveStateError::Mismatch(
+                "data after the end of the state".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Take and load save states when requested through the channel, at the start of a frame
+    pub fn set_state_commands(&mut self, commands: Receiver<StateCommand>) {
+        self.state_commands = Some(commands);
+    }
+
+    fn run_state_commands(&mut self) {
+        let commands: Vec<StateCommand> = match &self.state_commands {
+            Some(commands) => commands.try_iter().collect(),
+            None => return,
+        };
+        for command in commands {
+            match command {
+                StateCommand::Save(path) => match fs::write(&path, self.save_state()) {
+                    Ok(()) => log::info!("saved state to {}", path.display()),
+                    Err(e) => log::error!("could not save state to {}: {}", path.display(), e),
+                },
+                StateCommand::Rewind => match self.rewind() {
+                    Ok(true) => log::info!("rewound to frame {}", self.memory.frame()),
+                    Ok(false) => log::info!("there is nothing to rewind to"),
+                    Err(e) => log::error!("could not rewind: {}", e),
+                },
+                StateCommand::Load(path) => {
+                    let result = fs::read(&path)
+                        .map_err(MainError::from)
+                        .and_then(|data| Ok(self.load_state(&data)?));
+                    match result {
+                        Ok(()) => log::info!("loaded state from {}", path.display()),
+                        Err(e) => log::error!("could not load {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep snapshots in a rewind buffer, they are taken when a frame starts
+    pub fn set_rewind_buffer(&mut self, buffer: RewindBuffer) {
+        self.rewind = Some(buffer);
+    }
+
+    fn take_snapshot(&mut self) {
+        let frame = self.memory.frame();
+        if self
+            .rewind
+            .as_ref()
+            .is_some_and(|rewind| rewind.is_due(frame))
+        {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(frame, state);
+            }
+        }
+    }
+
+    // Go back a second, to the newest snapshot that is at least that old
+    //
+    // Calling this again goes back further, until the oldest snapshot is reached. Returns false
+    // when there is no snapshot before the current frame. Like the state commands, this is best
+    // done when a frame starts.
+    pub fn rewind(&mut self) -> Result<bool, SaveStateError> {
+        let frame = self.memory.frame();
+        let target = frame.saturating_sub(FRAMES_PER_SECOND);
+        let snapshot = self
+            .rewind
+            .as_mut()
+            .and_then(|rewind| rewind.rewind_to(target));
+        match snapshot {
+            Some((snapshot_frame, state)) if snapshot_frame < frame => {
+                self.load_state(&state)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // Stop in an interactive debugger at breakpoints and when stepping through the code
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.memory.set_observer(Box::new(debugger.watchpoints()));
+        self.debugger = Some(debugger);
+    }
+
+    // Write a line with the registers and the instruction to the output before every instruction
+    pub fn set_trace(&mut self, output: Box<dyn io::Write + Send>) {
+        self.trace = Some(Trace(output));
+        self.debug = DebugMode::Emu;
+    }
+
+    // Called before every instruction, opens the debugger prompt when it wants to stop here
+    fn run_debugger(&mut self, ppu: &mut Ppu) {
+        if let Some(mut debugger) = self.debugger.take() {
+            if debugger.should_break(self) {
+                debugger.prompt(self, ppu);
+            }
+            self.debugger = Some(debugger);
+        }
+    }
+
+    // Assert or release the IRQ line for one of the interrupt sources
+    //
+    // The line stays asserted for as long as any of the sources asserts it. An IRQ is taken at
+    // the end of the current instruction when the I flag is clear.
+    pub fn set_irq_line(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.irq_line |= source.mask();
+        } else {
+            self.irq_line &= !source.mask();
+        }
+    }
+
+    // Returns true when at least one source is asserting the IRQ line
+    pub fn irq_line(&self) -> bool {
+        self.irq_line != 0
+    }
+
     // Push the process counter and stack pointer on the stack
+    //
+    // The break flag is pushed cleared to tell hardware interrupts apart from BRK.
     fn push_pc_and_status_on_stack(&mut self, ppu: &mut Ppu) -> Result<(), MemoryError> {
         self.memory.write(
             self.stack_pointer.get() as u16 + 0x0100,
@@ -521,7 +914,7 @@ impl Cpu {
         self.stack_pointer.decrement();
         self.memory.write(
             self.stack_pointer.get() as u16 + 0x0100,
-            self.status_register.get() | 0x10,
+            self.status_register.get() & !0x10,
             ppu,
         )?;
         self.stack_pointer.decrement();
@@ -536,13 +929,13 @@ impl Cpu {
         if self.nmi_line_triggered {
             return_value = InterruptState::NMI;
             log::debug!("Interrupt state NMI polled");
-        } else if self.irq_line_triggered {
+        } else if self.irq_line() {
             return_value = InterruptState::IRQ;
             log::debug!("Interrupt state IRQ polled");
         } else {
             return_value = InterruptState::NormalOperation;
         }
-        self.irq_line_triggered = false;
+        // the IRQ line is level sensitive, so only the NMI edge is consumed here
         self.nmi_line_triggered = false;
         return_value
     }
@@ -557,7 +950,10 @@ impl Cpu {
         log::debug!("hibyte: {:02X}", hibyte);
         self.program_counter.set_lobyte(lobyte);
         self.program_counter.set_hibyte(hibyte);
-        self.stack_pointer.set(0xFD);
+        // the reset sequence decrements the stack pointer three times without writing, from 0 at
+        // power-on
+        self.stack_pointer
+            .set(self.stack_pointer.get().wrapping_sub(3));
         self.status_register
             .set_bit(StatusRegisterBit::Interrupt, true);
 
@@ -585,3 +981,364 @@ fn test_address_offset() {
         255
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tudelft_nes_ppu::Mirroring;
+
+    // Builds an NROM image from a list of code fragments and the addresses they are placed at
+    fn test_rom(code: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        let mut prg = vec![0xEA; 0x4000];
+        for (address, bytes) in code {
+            let start = (*address & 0x3FFF) as usize;
+            prg[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        rom
+    }
+
+    // Reset at $C000, IRQ handler at $C100 storing the accumulator to $00 after loading `irq_load`
+    fn irq_test_rom(reset: &[u8], irq_load: &[u8]) -> Vec<u8> {
+        let mut irq = irq_load.to_vec();
+        irq.extend([0x85, 0x00, 0x4C, irq.len() as u8 + 2, 0xC1]); // STA $00, JMP to itself
+        test_rom(&[
+            (0xC000, reset),
+            (0xC100, &irq),
+            (0xFFFA, &[0x00, 0xC2, 0x00, 0xC0, 0x00, 0xC1]),
+        ])
+    }
+
+    fn run(cpu: &mut Cpu, ppu: &mut Ppu, ticks: usize) {
+        for _ in 0..ticks {
+            cpu.tick(ppu).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_irq_is_serviced_through_vector() {
+        // CLI, JMP $C001
+        let rom = irq_test_rom(&[0x58, 0x4C, 0x01, 0xC0], &[0xA9, 0x42]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 30000);
+
+        assert_eq!(cpu.memory_read(0x00), 0x42);
+        assert!(cpu.status_register.get_bit(StatusRegisterBit::Interrupt));
+        // The return address and the status are pushed with the break flag cleared
+        assert_eq!(cpu.memory_read(0x01FD), 0xC0);
+        assert_eq!(cpu.memory_read(0x01FC), 0x01);
+        assert_eq!(cpu.memory_read(0x01FB) & 0x10, 0);
+    }
+
+    #[test]
+    fn test_irq_honours_interrupt_disable() {
+        // JMP $C000, the I flag is still set from reset
+        let rom = irq_test_rom(&[0x4C, 0x00, 0xC0], &[0xA9, 0x42]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 30000);
+
+        assert_eq!(cpu.memory_read(0x00), 0x00);
+        assert!(cpu.irq_line());
+    }
+
+    #[test]
+    fn test_frame_counter_irq_is_acknowledged() {
+        // CLI, JMP $C001, with an IRQ handler reading $4015
+        let rom = irq_test_rom(&[0x58, 0x4C, 0x01, 0xC0], &[0xAD, 0x15, 0x40]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 29000);
+        assert!(!cpu.irq_line());
+        assert_eq!(cpu.memory_read(0x00), 0x00);
+
+        run(&mut cpu, &mut ppu, 1000);
+        assert_eq!(cpu.memory_read(0x00) & 0x40, 0x40);
+        assert!(!cpu.irq_line());
+    }
+
+    // Reset code enabling the DMC interrupt and playing a one byte sample from $C000
+    const DMC_SAMPLE: [u8; 14] = [
+        0xA9, 0x80, 0x8D, 0x10, 0x40, // LDA #$80, STA $4010
+        0xA9, 0x10, 0x8D, 0x15, 0x40, // LDA #$10, STA $4015
+        0x58, 0x4C, 0x0B, 0xC0, // CLI, JMP $C00B
+    ];
+
+    #[test]
+    fn test_dmc_dma_stalls_cpu() {
+        let rom = irq_test_rom(&DMC_SAMPLE, &[0xAD, 0x15, 0x40]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        while cpu.dma_stall_cycles == 0 {
+            cpu.tick(&mut ppu).unwrap();
+        }
+
+        // The fetch happens while the CPU executes the JMP loop, which never writes
+        assert_eq!(cpu.dma_stall_cycles, 4);
+        let program_counter = cpu.program_counter.get();
+        let current_cycle = cpu.current_cycle;
+        let total_cycles = cpu.total_cycles;
+        run(&mut cpu, &mut ppu, 4);
+        assert_eq!(cpu.program_counter.get(), program_counter);
+        assert_eq!(cpu.current_cycle, current_cycle);
+        assert_eq!(cpu.total_cycles, total_cycles + 4);
+    }
+
+    #[test]
+    fn test_dmc_irq_at_sample_end() {
+        let rom = irq_test_rom(&DMC_SAMPLE, &[0xAD, 0x15, 0x40]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 100);
+
+        // $4015 reports the DMC interrupt but the sample is done
+        assert_eq!(cpu.memory_read(0x00) & 0x90, 0x80);
+        assert!(cpu.irq_line());
+    }
+
+    // Strobes the first controller, adds its first bit to $00 and counts the loops in $01
+    const READ_CONTROLLER_LOOP: [u8; 23] = [
+        0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1, STA $4016
+        0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0, STA $4016
+        0xAD, 0x16, 0x40, 0x29, 0x01, // LDA $4016, AND #1
+        0x18, 0x65, 0x00, 0x85, 0x00, // CLC, ADC $00, STA $00
+        0x4C, 0x00, 0xC0, // JMP $C000
+    ];
+
+    #[test]
+    fn test_movie_playback_is_bit_exact() {
+        use crate::memory::input::InputScript;
+
+        let rom = irq_test_rom(&READ_CONTROLLER_LOOP, &[]);
+        let frame_cycles = 341 * 262 / 3;
+        let script = Arc::new(InputScript::parse("1 a\n3+2 a").unwrap());
+
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        let recorder = MovieRecorder::new(Movie::new("test", cpu.rom_checksum()));
+        let source = ScriptedInput::new(script, Port::One);
+        let source = recorder.source(Port::One, Box::new(source));
+        cpu.set_input_device(Port::One, Box::new(Controller::new(Box::new(source))));
+        cpu.record_movie(recorder.clone());
+        // the reset is requested in frame 2 and recorded when frame 3 starts
+        run(&mut cpu, &mut ppu, frame_cycles * 2 + 100);
+        cpu.reset();
+        run(&mut cpu, &mut ppu, frame_cycles * 4);
+
+        let movie = Movie::parse(&recorder.movie().to_fm2()).unwrap();
+        assert_eq!(movie.frames[3].commands, COMMAND_RESET);
+        let mut replay = Cpu::get_cpu(&rom).unwrap();
+        replay.play_movie(&movie).unwrap();
+        run(&mut replay, &mut ppu, frame_cycles * 6 + 100);
+        assert_ne!(cpu.memory_read(0x00), 0);
+        assert_eq!(replay.memory_read(0x00), cpu.memory_read(0x00));
+        assert_eq!(replay.program_counter.get(), cpu.program_counter.get());
+        assert_eq!(replay.total_cycles, cpu.total_cycles);
+
+        let other = Movie::new("other", [0; 16]);
+        assert_eq!(replay.play_movie(&other), Err(MovieError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_save_state_resumes_exactly() {
+        let rom = irq_test_rom(&DMC_SAMPLE, &[0xAD, 0x15, 0x40]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 50);
+        let saved = cpu.save_state();
+        run(&mut cpu, &mut ppu, 3000);
+        let expected = cpu.save_state();
+        assert_ne!(saved, expected);
+
+        cpu.load_state(&saved).unwrap();
+        assert_eq!(cpu.save_state(), saved);
+        run(&mut cpu, &mut ppu, 3000);
+        assert_eq!(cpu.save_state(), expected);
+        assert_eq!(cpu.memory_read(0x00) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_invalid_save_states_are_rejected() {
+        let rom = irq_test_rom(&DMC_SAMPLE, &[0xAD, 0x15, 0x40]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        run(&mut cpu, &mut ppu, 100);
+        let saved = cpu.save_state();
+
+        let mut other = Cpu::get_cpu(&irq_test_rom(&DMC_SAMPLE, &[])).unwrap();
+        assert_eq!(other.load_state(&saved), Err(SaveStateError::RomMismatch));
+
+        // A truncated state leaves the CPU as it was
+        run(&mut cpu, &mut ppu, 100);
+        let current = cpu.save_state();
+        assert_eq!(
+            cpu.load_state(&saved[..saved.len() - 1]),
+            Err(SaveStateError::UnexpectedEnd)
+        );
+        assert_eq!(cpu.save_state(), current);
+    }
+
+    #[test]
+    fn test_rewind_goes_back_a_second() {
+        let rom = irq_test_rom(&READ_CONTROLLER_LOOP, &[]);
+        let mut cpu = Cpu::get_cpu(&rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        cpu.set_rewind_buffer(RewindBuffer::new(10, 100));
+        assert_eq!(cpu.rewind(), Ok(false));
+
+        // the snapshots are taken before the first cycle of a frame
+        let mut states = Vec::new();
+        while cpu.memory.frame() < 75 {
+            if cpu.memory.frame() != cpu.state_frame {
+                states.push((cpu.memory.frame(), cpu.save_state()));
+            }
+            cpu.tick(&mut ppu).unwrap();
+        }
+        assert_eq!(cpu.rewind(), Ok(true));
+        assert_eq!(cpu.memory.frame(), 10);
+        let (_, expected) = states.iter().find(|(frame, _)| *frame == 10).unwrap();
+        assert_eq!(&cpu.save_state(), expected);
+        assert_eq!(cpu.rewind(), Ok(false));
+    }
+
+    // Output of a debugger that the test can read back
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Calls a subroutine at $C010 that calls another at $C020, then loops at $C007
+    fn subroutine_rom() -> Vec<u8> {
+        test_rom(&[
+            (
+                0xC000,
+                &[0x20, 0x10, 0xC0, 0xA9, 0x01, 0x85, 0x00, 0x4C, 0x07, 0xC0],
+            ), // JSR $C010, LDA #1, STA $00, JMP $C007
+            (0xC010, &[0xA2, 0x05, 0x20, 0x20, 0xC0, 0x60]), // LDX #5, JSR $C020, RTS
+            (0xC020, &[0xA0, 0x07, 0x60]),                   // LDY #7, RTS
+            (0xFFFC, &[0x00, 0xC0]),
+        ])
+    }
+
+    // Runs a program under the debugger with the given commands, returns the addresses it
+    // stopped at and what it printed
+    fn debug_session(rom: &[u8], commands: &str) -> (Cpu, Vec<String>, String) {
+        let mut cpu = Cpu::get_cpu(rom).unwrap();
+        let mut ppu = Ppu::new(Mirroring::Horizontal);
+        let output = SharedOutput::default();
+        let input = io::Cursor::new(commands.as_bytes().to_vec());
+        cpu.set_debugger(Debugger::new(Box::new(input), Box::new(output.clone())));
+        run(&mut cpu, &mut ppu, 200);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let stops = output
+            .lines()
+            .map(|line| line.trim_start_matches("(debug) "))
+            .filter(|line| line.len() > 6 && &line[4..6] == "  ")
+            .map(|line| line[..4].to_string())
+            .collect();
+        (cpu, stops, output)
+    }
+
+    #[test]
+    fn test_debugger_steps_through_subroutines() {
+        let (cpu, stops, _) = debug_session(&subroutine_rom(), "s\ns\ns\nf\nn\nn\nn\n");
+        assert_eq!(
+            stops,
+            ["C000", "C010", "C012", "C020", "C015", "C003", "C005", "C007"]
+        );
+        assert_eq!(cpu.y_register.get(), 7);
+        assert_eq!(cpu.memory_read(0x00), 0x01);
+
+        // Stepping over a JSR runs the whole subroutine, finishing outside of one runs on
+        let (cpu, stops, _) = debug_session(&subroutine_rom(), "n\nf\n");
+        assert_eq!(stops, ["C000", "C003"]);
+        assert_eq!(cpu.x_register.get(), 5);
+    }
+
+    #[test]
+    fn test_debugger_breakpoints_and_edits() {
+        let (cpu, stops, output) = debug_session(
+            &subroutine_rom(),
+            "b c020\nb C007\nc\nset x 42\nset c 1\nr\nc\nw 10 99 98\nm 0 12\n",
+        );
+        assert_eq!(stops, ["C000", "C020", "C007"]);
+        assert!(output.contains("A:00 X:42 Y:00 SP:F9 P:25 nv--dIzC CYC:21"));
+        assert!(
+            output.contains("0000: 01 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n0010: 99 98\n")
+        );
+        assert_eq!(cpu.x_register.get(), 0x42);
+        assert_eq!(cpu.memory_read(0x11), 0x98);
+    }
+
+    #[test]
+    fn test_debugger_watchpoints() {
+        let rom = test_rom(&[
+            (
+                0xC000,
+                &[
+                    0xA9, 0x02, 0x8D, 0x14, 0x40, // LDA #2, STA $4014
+                    0xA9, 0x00, 0x85, 0x75, // LDA #0, STA $75
+                    0x8D, 0x01, 0x20, // STA $2001
+                    0x4C, 0x0C, 0xC0, // JMP $C00C
+                ],
+            ),
+            (0xFFFC, &[0x00, 0xC0]),
+        ]);
+        let commands = "watch r 0203\nwatch w 875 0\nwatch w 2001\nwatch x c000-cfff 4c\n\
+                        c\nc\nc\nunwatch 4\nwatch\nc\n";
+        let (_, stops, output) = debug_session(&rom, commands);
+        assert_eq!(stops, ["C000", "C005", "C009", "C00C"]);
+        assert!(output.contains(
+            "Watchpoint 1: OAM DMA read of 00 from 0203 (last instruction at C002)\nC005"
+        ));
+        assert!(output.contains("Watchpoint 2: write of 00 to 0075 (last instruction at C007)"));
+        assert!(output.contains(
+            "Watchpoint 3: write of 00 to 2001 (last instruction at C009)\n\
+             Watchpoint 4: execute 4C at C00C\n"
+        ));
+        assert!(output.contains("1: read 0203\n2: write 0875 with 00\n3: write 2001\n"));
+    }
+
+    #[test]
+    fn test_trace_matches_nestest_log() {
+        let rom = include_bytes!("../../roms/nestest.nes");
+        let expected = include_str!("../../expected-output/nestest.log");
+        let mut cpu = Cpu::get_cpu(rom).unwrap();
+        let mut ppu = Ppu::new(cpu.ppu_mirroring());
+        let output = SharedOutput::default();
+        cpu.set_trace(Box::new(output.clone()));
+
+        // After the reset sequence nestest runs all tests without a screen when started at $C000
+        while cpu.interrupt_state != InterruptState::NormalOperation {
+            cpu.tick(&mut ppu).unwrap();
+        }
+        cpu.set_program_counter(0xC000);
+        let lines = expected.lines().count() as u64;
+        while cpu.instructions_executed < lines {
+            cpu.tick(&mut ppu).unwrap();
+        }
+
+        // the log uses the nestest names of two unofficial instructions
+        let output = String::from_utf8(output.0.lock().unwrap().clone())
+            .unwrap()
+            .replace("  USBC ", "  SBC ")
+            .replace("  ISC ", "  ISB ");
+        assert!(output.lines().count() as u64 >= lines);
+        for (number, (line, expected)) in output.lines().zip(expected.lines()).enumerate() {
+            assert_eq!(line, expected, "line {} of the trace differs", number + 1);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::memory::observer::{Access, AccessKind, AccessSource, MemoryObserver};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// The accesses a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Reads and writes
+    Execute,
+}
+
+// Stops the debugger when an address in a range is accessed, optionally only with a given value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16,          // Last address of the range
+    pub value: Option<u8>, // Only stop when this value is read or written, or this opcode runs
+}
+
+impl Watchpoint {
+    // Returns true when the address or one of its mirrors is in the range
+    //
+    // A watchpoint on $0075 also stops on $0875, one on $2007 also on $3FFF, and the other way
+    // around.
+    fn contains(&self, address: u16) -> bool {
+        let (first, period, limit) = match address {
+            ..0x2000 => (address & 0x07FF, 0x800, 0x2000),
+            0x2000..0x4000 => (address & 0x2007, 8, 0x4000),
+            _ => return (self.start..=self.end).contains(&address),
+        };
+        let (first, start) = (first as u32, self.start as u32);
+        let mirror = first + start.saturating_sub(first).div_ceil(period) * period;
+        mirror < limit && mirror <= self.end as u32
+    }
+
+    fn matches(&self, access: &Access) -> bool {
+        let kind = matches!(
+            (self.kind, access.kind),
+            (WatchKind::Access, _)
+                | (WatchKind::Read, AccessKind::Read)
+                | (WatchKind::Write, AccessKind::Write)
+        );
+        kind && self.contains(access.address) && self.value.is_none_or(|v| v == access.value)
+    }
+
+    fn matches_execute(&self, address: u16, opcode: u8) -> bool {
+        self.kind == WatchKind::Execute
+            && self.contains(address)
+            && self.value.is_none_or(|v| v == opcode)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+            WatchKind::Execute => "execute",
+        };
+        write!(f, "{} {:04X}", kind, self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        if let Some(value) = self.value {
+            write!(f, " with {:02X}", value)?;
+        }
+        Ok(())
+    }
+}
+
+// An access that hit a watchpoint, with the number of the watchpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub watchpoint: usize,
+    pub access: Access,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self.access.source {
+            AccessSource::Cpu => "",
+            AccessSource::OamDma => "OAM DMA ",
+            AccessSource::DmcDma => "DMC DMA ",
+        };
+        let (kind, preposition) = match self.access.kind {
+            AccessKind::Read => ("read", "from"),
+            AccessKind::Write => ("write", "to"),
+        };
+        write!(
+            f,
+            "Watchpoint {}: {}{} of {:02X} {} {:04X}",
+            self.watchpoint, source, kind, self.access.value, preposition, self.access.address
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct WatchList {
+    watchpoints: Vec<Option<Watchpoint>>, // Removed watchpoints leave a gap, to keep the numbers
+    hits: Vec<Hit>,                       // Accesses since the debugger last looked
+}
+
+// The watchpoints of the debugger, shared with the memory which reports every access to them
+#[derive(Debug, Clone, Default)]
+pub struct Watchpoints {
+    list: Arc<Mutex<WatchList>>,
+}
+
+impl Watchpoints {
+    fn lock(&self) -> MutexGuard<'_, WatchList> {
+        self.list.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Adds a watchpoint and returns its number
+    pub fn add(&self, watchpoint: Watchpoint) -> usize {
+        let mut list = self.lock();
+        list.watchpoints.push(Some(watchpoint));
+        list.watchpoints.len()
+    }
+
+    // Removes a watchpoint by its number, returns false when there is no such watchpoint
+    pub fn remove(&self, number: usize) -> bool {
+        let mut list = self.lock();
+        match number
+            .checked_sub(1)
+            .and_then(|i| list.watchpoints.get_mut(i))
+        {
+            Some(watchpoint) => watchpoint.take().is_some(),
+            None => false,
+        }
+    }
+
+    // Returns the watchpoints with their numbers
+    pub fn list(&self) -> Vec<(usize, Watchpoint)> {
+        self.lock()
+            .watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, watchpoint)| Some((i + 1, watchpoint.clone()?)))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        let mut list = self.lock();
+        list.watchpoints.clear();
+        list.hits.clear();
+    }
+
+    // Returns the numbers of the execute watchpoints the instruction at an address hits
+    pub fn executed(&self, address: u16, opcode: u8) -> Vec<usize> {
+        self.lock()
+            .watchpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| {
+                w.as_ref()
+                    .is_some_and(|w| w.matches_execute(address, opcode))
+            })
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    // Returns and forgets the accesses that hit a watchpoint
+    pub fn take_hits(&self) -> Vec<Hit> {
+        std::mem::take(&mut self.lock().hits)
+    }
+}
+
+impl MemoryObserver for Watchpoints {
+    fn access(&mut self, access: Access) {
+        let mut list = self.lock();
+        let list = &mut *list;
+        for (i, watchpoint) in list.watchpoints.iter().enumerate() {
+            if watchpoint.as_ref().is_some_and(|w| w.matches(&access)) {
+                list.hits.push(Hit {
+                    watchpoint: i + 1,
+                    access,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(kind: AccessKind, address: u16, value: u8) -> Access {
+        Access {
+            kind,
+            source: AccessSource::Cpu,
+            address,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_watchpoints_match_accesses() {
+        let mut watchpoints = Watchpoints::default();
+        let write_zero = watchpoints.add(Watchpoint {
+            kind: WatchKind::Write,
+            start: 0x0075,
+            end: 0x0075,
+            value: Some(0),
+        });
+        let registers = watchpoints.add(Watchpoint {
+            kind: WatchKind::Access,
+            start: 0x2000,
+            end: 0x2007,
+            value: None,
+        });
+        watchpoints.access(access(AccessKind::Write, 0x0075, 1));
+        watchpoints.access(access(AccessKind::Read, 0x0075, 0));
+        assert!(watchpoints.take_hits().is_empty());
+
+        // The mirrors of RAM and the PPU registers hit too
+        watchpoints.access(access(AccessKind::Write, 0x0875, 0));
+        watchpoints.access(access(AccessKind::Read, 0x3FFA, 0x80));
+        watchpoints.access(access(AccessKind::Read, 0x4000, 0x80));
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].watchpoint, write_zero);
+        assert_eq!(hits[1].watchpoint, registers);
+        assert_eq!(hits[1].to_string(), "Watchpoint 2: read of 80 from 3FFA");
+
+        assert!(watchpoints.remove(write_zero));
+        assert!(!watchpoints.remove(write_zero));
+        watchpoints.access(access(AccessKind::Write, 0x0075, 0));
+        assert!(watchpoints.take_hits().is_empty());
+        assert_eq!(watchpoints.list().len(), 1);
+        assert_eq!(watchpoints.list()[0].0, registers);
+
+        let code = watchpoints.add(Watchpoint {
+            kind: WatchKind::Execute,
+            start: 0xC000,
+            end: 0xC0FF,
+            value: Some(0x60),
+        });
+        assert_eq!(watchpoints.executed(0xC010, 0x60), [code]);
+        assert!(watchpoints.executed(0xC010, 0x40).is_empty());
+        watchpoints.access(access(AccessKind::Read, 0xC010, 0x60));
+        assert!(watchpoints.take_hits().is_empty());
+    }
+}
@@ -3,13 +3,53 @@ use thiserror::Error;
 #[derive(Debug, Error, PartialEq)]
 pub enum RomError {
     #[error("Unknown Mapper Error: Mapper {0} is not implemented. Details: {1}")]
-    UnknownMapper(u8, String),
+    UnknownMapper(u16, String),
     #[error("Unknown Address Error: Rom address not in the right range. Details: {0}")]
     UnknownAddress(String),
     #[error("Header signature does not match specification. Details: {0}")]
     IncorrectSignature(String),
-    #[error("Given amount of data does not match header. Details: {0}")]
-    IncorrectDataSize(String),
+    #[error("File of {0} bytes is too short to contain an iNES header")]
+    TooShort(usize),
+    #[error("Header specifies no program ROM")]
+    NoProgramRom,
+    #[error("Program ROM is truncated: {0} bytes expected, {1} found")]
+    TruncatedProgramRom(usize, usize),
+    #[error("Character ROM is truncated: {0} bytes expected, {1} found")]
+    TruncatedCharacterRom(usize, usize),
+    #[error("File has {0} bytes of unexpected data after the ROM")]
+    TrailingData(usize),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SaveStateError {
+    #[error("Save state ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("Save state does not match the emulated hardware. Details: {0}")]
+    Mismatch(String),
+    #[error("File is not a save state")]
+    NotASaveState,
+    #[error("Save state has version {0}, which is not supported")]
+    UnsupportedVersion(u16),
+    #[error("Save state was saved with another ROM")]
+    RomMismatch,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ScriptError {
+    #[error("Line {0} of the input script is invalid: {1}")]
+    InvalidLine(usize, String),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum MovieError {
+    #[error("Line {0} of the movie is invalid: {1}")]
+    InvalidLine(usize, String),
+    #[error("Movie has no ROM checksum")]
+    MissingChecksum,
+    #[error("Movie was recorded with another ROM")]
+    ChecksumMismatch,
+    #[error("Movie uses {0}, which is not supported")]
+    Unsupported(String),
 }
 
 #[derive(Debug, Error)]
@@ -49,6 +89,18 @@ pub enum MainError {
 
     #[error("Opcode Error occurred. Details: {0}")]
     Opcode(String),
+
+    #[error("IO Error occurred: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Input Script Error occurred: {0}")]
+    Script(#[from] ScriptError),
+
+    #[error("Movie Error occurred: {0}")]
+    Movie(#[from] MovieError),
+
+    #[error("Save State Error occurred: {0}")]
+    SaveState(#[from] SaveStateError),
 }
 
 // Implement `From` conversions, passing along the string context from the source errors
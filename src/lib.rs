@@ -0,0 +1,9 @@
+pub mod apu;
+pub mod audio;
+pub mod checksum;
+pub mod cpu;
+pub mod error;
+pub mod memory;
+pub mod movie;
+pub mod rewind;
+pub mod savestate;
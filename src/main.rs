@@ -1,42 +1,435 @@
-use cpu::Cpu;
-use error::MainError;
 use log::LevelFilter;
+use nes_emulator::audio::{AudioOutput, WavSink, SAMPLE_RATE_44100};
+use nes_emulator::cpu::disassembler::{disassemble_bank, format_listing, vector_labels};
+use nes_emulator::cpu::{Cpu, Debugger};
+use nes_emulator::error::{MainError, MovieError};
+use nes_emulator::memory::input::{
+    DeviceKind, InputScript, InputSource, Port, PpuJoypad, ScriptedInput,
+};
+use nes_emulator::memory::Cartridge;
+use nes_emulator::movie::{Movie, MovieRecorder};
+use nes_emulator::rewind::{RewindBuffer, FRAMES_PER_SECOND};
+use nes_emulator::savestate::StateCommand;
+use std::collections::BTreeMap;
 use std::env;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use tudelft_nes_ppu::{run_cpu, Mirroring};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use tudelft_nes_ppu::{run_cpu, run_cpu_headless_for};
 use tudelft_nes_test::TestableCpu;
 use tudelft_nes_test::ROM_NROM_TEST;
 
-mod cpu;
-mod error;
-mod memory;
+// Number of PPU dots in a frame, the CPU runs at a third of the PPU clock
+const PPU_DOTS_PER_FRAME: usize = 341 * 262;
 
-fn run(file_bytes: &[u8]) -> Result<(), MainError> {
+// The rewind buffer takes a snapshot every 6 frames and goes back up to 30 seconds
+const REWIND_INTERVAL: u64 = 6;
+const REWIND_SECONDS: u64 = 30;
+
+// Size of the PRG ROM banks the disassembler shows, the unit of PRG ROM in an iNES header
+const PRG_BANK_SIZE: usize = 0x4000;
+
+const USAGE: &str = "Usage: nes-emulator [ROM] [--record-audio FILE] [--headless FRAMES] \
+                     [--port1 DEVICE] [--port2 DEVICE] [--input-script FILE]
+                     [--record-movie FILE] [--play-movie FILE] [--load-state FILE] [--debug]
+                     [--trace FILE]
+       nes-emulator disasm ROM [--bank N] [--from ADDR]
+DEVICE is one of pad, zapper, four-score, vaus or none
+While running, type `save N` or `load N` to use quick save slot N (0-9), or `rewind` to go back
+a second. With --debug the terminal is used by the debugger instead, type `help` at its prompt";
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    rom: Option<String>,
+    record_audio: Option<String>,   // WAV file the audio is recorded to
+    headless_frames: Option<usize>, // Run without a window for this many frames
+    port_1: Option<DeviceKind>,     // Device plugged into the first controller port
+    port_2: Option<DeviceKind>,     // Device plugged into the second controller port
+    input_script: Option<String>,   // Script the input is read from instead of the keyboard
+    record_movie: Option<String>,   // FM2 file the input is recorded to
+    play_movie: Option<String>,     // FM2 file the input is played back from
+    load_state: Option<String>,     // Save state to start from instead of power-on
+    debug: bool,                    // Stop at the first instruction in the debugger
+    trace: Option<String>,          // File a line per executed instruction is written to
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record-audio" => {
+                let path = args.next().ok_or("--record-audio expects a file name")?;
+                options.record_audio = Some(path.clone());
+            }
+            "--headless" => {
+                let frames = args.next().ok_or("--headless expects a number of frames")?;
+                let frames = frames
+                    .parse()
+                    .map_err(|_| format!("Invalid number of frames: {}", frames))?;
+                options.headless_frames = Some(frames);
+            }
+            "--input-script" => {
+                let path = args.next().ok_or("--input-script expects a file name")?;
+                options.input_script = Some(path.clone());
+            }
+            "--record-movie" => {
+                let path = args.next().ok_or("--record-movie expects a file name")?;
+                options.record_movie = Some(path.clone());
+            }
+            "--play-movie" => {
+                let path = args.next().ok_or("--play-movie expects a file name")?;
+                options.play_movie = Some(path.clone());
+            }
+            "--load-state" => {
+                let path = args.next().ok_or("--load-state expects a file name")?;
+                options.load_state = Some(path.clone());
+            }
+            "--debug" => options.debug = true,
+            "--trace" => {
+                let path = args.next().ok_or("--trace expects a file name")?;
+                options.trace = Some(path.clone());
+            }
+            "--port1" | "--port2" => {
+                let device = args
+                    .next()
+                    .ok_or(format!("{} expects an input device", arg))?
+                    .parse()?;
+                if arg == "--port1" {
+                    options.port_1 = Some(device);
+                } else {
+                    options.port_2 = Some(device);
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if options.rom.is_none() => options.rom = Some(arg.clone()),
+            _ => return Err("Invalid number of arguments".to_string()),
+        }
+    }
+    if options.play_movie.is_some()
+        && (options.record_movie.is_some() || options.input_script.is_some())
+    {
+        return Err("--play-movie can't be combined with other input".to_string());
+    }
+    if options.load_state.is_some()
+        && (options.play_movie.is_some() || options.record_movie.is_some())
+    {
+        return Err("Movies start at power-on, not from a save state".to_string());
+    }
+    Ok(options)
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct DisasmOptions {
+    rom: String,
+    bank: Option<usize>, // Only disassemble this 16 KiB bank of PRG ROM
+    from: Option<u16>,   // Start at this address instead of the start of the bank
+}
+
+fn parse_disasm_args(args: &[String]) -> Result<DisasmOptions, String> {
+    let mut rom = None;
+    let mut options = DisasmOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => {
+                let bank = args.next().ok_or("--bank expects a bank number")?;
+                let bank = bank
+                    .parse()
+                    .map_err(|_| format!("Invalid bank number: {}", bank))?;
+                options.bank = Some(bank);
+            }
+            "--from" => {
+                let address = args.next().ok_or("--from expects an address")?;
+                let digits = address
+                    .strip_prefix('$')
+                    .or_else(|| address.strip_prefix("0x"))
+                    .unwrap_or(address);
+                let address = u16::from_str_radix(digits, 16)
+                    .map_err(|_| format!("Invalid address: {}", address))?;
+                options.from = Some(address);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom.is_none() => rom = Some(arg.clone()),
+            _ => return Err("Invalid number of arguments".to_string()),
+        }
+    }
+    options.rom = rom.ok_or("disasm expects a ROM")?;
+    Ok(options)
+}
+
+// Disassembles the PRG ROM of a ROM file, one bank after the other
+//
+// Without knowing the state of the mapper, every bank is shown at $8000 except the last one,
+// which ends at $FFFF where most mappers keep it. The interrupt vectors are read from that
+// bank, and the code they point to is labelled.
+fn disassemble_rom(rom_bytes: &[u8], options: &DisasmOptions) -> Result<String, String> {
+    let prg_rom = Cartridge::prg_rom(rom_bytes).map_err(|e| e.to_string())?;
+    let banks: Vec<&[u8]> = prg_rom.chunks(PRG_BANK_SIZE).collect();
+    let last = banks.len() - 1;
+    if let Some(bank) = options.bank.filter(|bank| *bank > last) {
+        return Err(format!(
+            "Bank {} does not exist, the ROM has {} banks",
+            bank,
+            last + 1
+        ));
+    }
+    let mut listing = String::new();
+    for (index, bank) in banks.iter().enumerate() {
+        let origin = if index == last {
+            (0x10000 - bank.len()) as u16
+        } else {
+            0x8000
+        };
+        let end = usize::from(origin) + bank.len();
+        let from = options.from.unwrap_or(origin);
+        if options.bank.is_some_and(|selected| selected != index)
+            || !(usize::from(origin)..end).contains(&usize::from(from))
+        {
+            continue;
+        }
+        let labels = if index == last {
+            vector_labels(bank)
+        } else {
+            BTreeMap::new()
+        };
+        listing.push_str(&format!(
+            "; bank {} at ${:04X}-${:04X}\n",
+            index,
+            origin,
+            end - 1
+        ));
+        listing.push_str(&format_listing(
+            &disassemble_bank(bank, origin, from),
+            &labels,
+        ));
+    }
+    if listing.is_empty() {
+        return Err("No bank contains the start address".to_string());
+    }
+    Ok(listing)
+}
+
+// Parses a quick save, load or rewind command typed while the emulator runs, like `save 3`
+//
+// The slots are files next to the ROM, `game.state3` for slot 3 of `game.nes`.
+fn parse_state_command(line: &str, rom: &Path) -> Result<StateCommand, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let slot = match words.next() {
+        Some(slot) => slot
+            .parse::<u8>()
+            .ok()
+            .filter(|slot| *slot < 10)
+            .ok_or(format!("Invalid save slot: {}", slot))?,
+        None => 0,
+    };
+    let path = rom.with_extension(format!("state{}", slot));
+    match command {
+        "save" => Ok(StateCommand::Save(path)),
+        "load" => Ok(StateCommand::Load(path)),
+        "rewind" => Ok(StateCommand::Rewind),
+        _ => Err(format!(
+            "Unknown command: {} (expected save, load or rewind)",
+            line.trim()
+        )),
+    }
+}
+
+// Reads quick save, load and rewind commands from the terminal, the CPU runs them when a frame starts
+fn read_state_commands(rom: PathBuf, commands: Sender<StateCommand>) {
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_state_command(&line, &rom) {
+                Ok(command) => {
+                    if commands.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    });
+}
+
+fn run(file_bytes: &[u8], options: &Options) -> Result<(), MainError> {
     env_logger::builder().filter_level(LevelFilter::Info).init();
 
-    let cpu = Cpu::get_cpu(file_bytes)?;
+    let mut cpu = Cpu::get_cpu(file_bytes)?;
+
+    if let Some(path) = &options.rom {
+        cpu.set_battery_file(Path::new(path).with_extension("sav"))?;
+    }
 
-    log::info!("running cpu");
-    run_cpu(cpu, Mirroring::Horizontal);
+    if let Some(path) = &options.record_audio {
+        let sink = WavSink::create(path, SAMPLE_RATE_44100)?;
+        cpu.set_audio_output(AudioOutput::new(SAMPLE_RATE_44100, Box::new(sink)));
+        log::info!("recording audio to {}", path);
+    }
+
+    let script = match &options.input_script {
+        Some(path) => {
+            let script = InputScript::parse(&fs::read_to_string(path)?)?;
+            log::info!("playing {} frames of input from {}", script.len(), path);
+            Some(Arc::new(script))
+        }
+        None => None,
+    };
+    // devices chosen on the command line are played with the keyboard of the window, unless there
+    // is a script. The first port keeps its pad when a script or movie recording replaces it.
+    let replaced = script.is_some() || options.record_movie.is_some();
+    let devices = [(Port::One, options.port_1), (Port::Two, options.port_2)].map(
+        |(port, device)| match device {
+            None if port == Port::One && replaced => (port, Some(DeviceKind::Pad)),
+            device => (port, device),
+        },
+    );
+
+    let recorder = match &options.record_movie {
+        Some(path) => {
+            let pads_only = devices
+                .iter()
+                .all(|(_, device)| device.is_none_or(|device| device == DeviceKind::Pad));
+            if !pads_only {
+                return Err(MovieError::Unsupported("devices other than pads".to_string()).into());
+            }
+            let rom_name = match &options.rom {
+                Some(rom) => Path::new(rom)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                None => "nrom-test".into(),
+            };
+            let mut movie = Movie::new(&rom_name, cpu.rom_checksum());
+            movie.ports = devices.map(|(_, device)| device.is_some());
+            log::info!("recording a movie to {}", path);
+            Some(MovieRecorder::create(path, movie)?)
+        }
+        None => None,
+    };
+
+    for (port, device) in devices {
+        if let Some(device) = device {
+            let mut source: Box<dyn InputSource> = match &script {
+                Some(script) => Box::new(ScriptedInput::new(script.clone(), port)),
+                None => Box::new(PpuJoypad),
+            };
+            if let Some(recorder) = &recorder {
+                source = Box::new(recorder.source(port, source));
+            }
+            cpu.set_input_device(port, device.create(port, source));
+        }
+    }
+    if let Some(recorder) = &recorder {
+        cpu.record_movie(recorder.clone());
+    }
+
+    if let Some(path) = &options.play_movie {
+        let movie = Movie::parse(&fs::read_to_string(path)?)?;
+        cpu.play_movie(&movie)?;
+        log::info!(
+            "playing {} frames of a movie from {}",
+            movie.frames.len(),
+            path
+        );
+    }
+
+    if let Some(path) = &options.trace {
+        // flushed per line, as closing the window exits without dropping the CPU
+        cpu.set_trace(Box::new(LineWriter::new(File::create(path)?)));
+        log::info!("writing an instruction trace to {}", path);
+    }
+
+    if let Some(path) = &options.load_state {
+        cpu.load_state(&fs::read(path)?)?;
+        log::info!("starting from the state saved in {}", path);
+    }
+    let (commands, receiver) = mpsc::channel();
+    cpu.set_state_commands(receiver);
+    let snapshots = REWIND_SECONDS * FRAMES_PER_SECOND / REWIND_INTERVAL;
+    cpu.set_rewind_buffer(RewindBuffer::new(REWIND_INTERVAL, snapshots as usize));
+    let rom = options.rom.as_deref().unwrap_or("nrom-test.nes");
+    if options.debug {
+        cpu.set_debugger(Debugger::stdio());
+    } else {
+        read_state_commands(PathBuf::from(rom), commands);
+    }
+
+    let mirroring = cpu.ppu_mirroring();
+    match options.headless_frames {
+        Some(frames) => {
+            log::info!("running cpu headless for {} frames", frames);
+            let cycles = frames * PPU_DOTS_PER_FRAME / 3;
+            run_cpu_headless_for(&mut cpu, mirroring, cycles)?;
+            if let Some(recorder) = &recorder {
+                recorder.finish()?;
+            }
+        }
+        None => {
+            log::info!("running cpu");
+            run_cpu(cpu, mirroring);
+        }
+    }
     Ok(())
 }
 
 fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() > 2 {
-        eprintln!("Invalid number of arguments");
-        return ExitCode::from(2);
+    if args.get(1).is_some_and(|command| command == "disasm") {
+        let options = match parse_disasm_args(&args[2..]) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        };
+        let listing = fs::read(&options.rom)
+            .map_err(|e| format!("Could not read {}: {}", options.rom, e))
+            .and_then(|rom_bytes| disassemble_rom(&rom_bytes, &options));
+        return match listing {
+            Ok(listing) => {
+                // the listing is often piped into a pager that may quit early
+                let _ = io::stdout().write_all(listing.as_bytes());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::from(1)
+            }
+        };
     }
 
-    let file_bytes = if args.len() == 2 {
-        fs::read(&args[1]).unwrap()
-    } else {
-        ROM_NROM_TEST.to_vec()
+    let options = match parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let file_bytes = match &options.rom {
+        Some(path) => match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", path, e);
+                return ExitCode::from(1);
+            }
+        },
+        None => ROM_NROM_TEST.to_vec(),
     };
 
-    match run(&file_bytes) {
+    match run(&file_bytes, &options) {
         Ok(_) => ExitCode::SUCCESS,
         Err(a) => {
             eprintln!("{}", a);
@@ -47,8 +440,9 @@ fn main() -> ExitCode {
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::Cpu;
+    use super::*;
     use log::LevelFilter;
+    use nes_emulator::cpu::Cpu;
     use tudelft_nes_test::{run_tests, TestSelector};
 
     #[test]
@@ -69,4 +463,88 @@ mod tests {
         let result = run_tests::<Cpu>(TestSelector::NESTEST);
         assert!(result.is_ok(), "TEST FAILED: {}", result.unwrap_err());
     }
+
+    #[test]
+    fn test_parse_args() {
+        let args = [
+            "game.nes",
+            "--record-audio",
+            "out.wav",
+            "--headless",
+            "60",
+            "--port2",
+            "zapper",
+            "--debug",
+            "--trace",
+            "trace.log",
+        ];
+        let args = args.map(String::from);
+        assert_eq!(
+            parse_args(&args),
+            Ok(Options {
+                rom: Some("game.nes".to_string()),
+                record_audio: Some("out.wav".to_string()),
+                headless_frames: Some(60),
+                port_1: None,
+                port_2: Some(DeviceKind::Zapper),
+                input_script: None,
+                record_movie: None,
+                play_movie: None,
+                load_state: None,
+                debug: true,
+                trace: Some("trace.log".to_string()),
+            })
+        );
+
+        assert!(parse_args(&["--headless".to_string()]).is_err());
+        assert!(parse_args(&["--port1".to_string(), "joystick".to_string()]).is_err());
+        assert!(parse_args(&["a.nes".to_string(), "b.nes".to_string()]).is_err());
+        let args = ["--play-movie", "a.fm2", "--record-movie", "b.fm2"].map(String::from);
+        assert!(parse_args(&args).is_err());
+        let args = ["--load-state", "a.state1", "--record-movie", "b.fm2"].map(String::from);
+        assert!(parse_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_state_command() {
+        let rom = Path::new("roms/game.nes");
+        assert_eq!(
+            parse_state_command("save 3", rom),
+            Ok(StateCommand::Save(PathBuf::from("roms/game.state3")))
+        );
+        assert_eq!(
+            parse_state_command(" load ", rom),
+            Ok(StateCommand::Load(PathBuf::from("roms/game.state0")))
+        );
+        assert_eq!(parse_state_command("rewind", rom), Ok(StateCommand::Rewind));
+        assert!(parse_state_command("load 10", rom).is_err());
+        assert!(parse_state_command("quit", rom).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_rom() {
+        let args = ["game.nes", "--bank", "1", "--from", "$C000"].map(String::from);
+        assert_eq!(
+            parse_disasm_args(&args),
+            Ok(DisasmOptions {
+                rom: "game.nes".to_string(),
+                bank: Some(1),
+                from: Some(0xC000),
+            })
+        );
+        assert!(parse_disasm_args(&[]).is_err());
+        assert!(parse_disasm_args(&["--from".to_string(), "x".to_string()]).is_err());
+
+        let options = parse_disasm_args(&["nrom-test.nes".to_string()]).unwrap();
+        let listing = disassemble_rom(ROM_NROM_TEST, &options).unwrap();
+        assert!(listing.starts_with("; bank 0 at $C000-$FFFF\n"));
+        assert!(listing.contains("\nreset:\nC000  "));
+        assert!(listing.contains("FFFC  00 C0     .dw reset\n"));
+
+        let options = DisasmOptions {
+            bank: Some(1),
+            ..options
+        };
+        assert!(disassemble_rom(ROM_NROM_TEST, &options).is_err());
+    }
 }
@@ -0,0 +1,59 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+// Number of CPU cycles between checks whether the RAM has to be written, about a second
+const FLUSH_INTERVAL: u32 = 1_789_773;
+
+// Keeps battery-backed PRG RAM in a .sav file
+//
+// There is no way to tell when the window of the emulator is closed, so besides when the
+// emulator stops, the RAM is written periodically whenever it changed.
+#[derive(Debug)]
+pub struct BatteryFile {
+    path: PathBuf,
+    saved: Vec<u8>, // The RAM as it was last read from or written to the file
+    cycles: u32,    // Cycles since the last check
+}
+
+impl BatteryFile {
+    // Opens a save file, returning it together with the RAM stored in it when it exists
+    pub fn open(path: PathBuf) -> io::Result<(Self, Option<Vec<u8>>)> {
+        let ram = match fs::read(&path) {
+            Ok(ram) => Some(ram),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let file = BatteryFile {
+            path,
+            saved: ram.clone().unwrap_or_default(),
+            cycles: 0,
+        };
+        Ok((file, ram))
+    }
+
+    // Called every CPU cycle
+    pub fn tick(&mut self, ram: &[u8]) {
+        self.cycles += 1;
+        if self.cycles >= FLUSH_INTERVAL {
+            self.cycles = 0;
+            if let Err(e) = self.flush(ram) {
+                log::warn!("could not write {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    // Writes the RAM to the file when it changed since it was last written
+    pub fn flush(&mut self, ram: &[u8]) -> io::Result<()> {
+        if self.saved == ram {
+            return Ok(());
+        }
+        // write to a temporary file first, so the save is not lost when writing fails halfway
+        let temporary = self.path.with_extension("sav.tmp");
+        fs::write(&temporary, ram)?;
+        fs::rename(&temporary, &self.path)?;
+        self.saved = ram.to_vec();
+        log::debug!("saved battery RAM to {}", self.path.display());
+        Ok(())
+    }
+}
@@ -1,35 +1,48 @@
+use super::{button_bits, buttons_from_bits, InputDevice, InputSource};
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
 use log::warn;
 use tudelft_nes_ppu::{Buttons, Ppu};
 
+// The standard NES controller
 #[derive(Debug)]
 pub struct Controller {
     strobe: bool,     // The least significant bit that is written to the controller
     buttons: Buttons, // Holds the button state from when strobe was last high
     read_index: u8,   // Index of the button being read
+    source: Box<dyn InputSource>, // Where the button state comes from
 }
 
 impl Controller {
-    // Writes to the controller input.
-    //
-    // Should be mapped to address $4016
-    // Only the least significant bit of the input will be used.
-    pub fn write(&mut self, byte: u8, ppu: &Ppu) {
-        self.strobe = (byte & 0b1) == 1;
-        self.clock_pulse(ppu);
+    pub fn new(source: Box<dyn InputSource>) -> Self {
+        Controller {
+            strobe: false,
+            buttons: Buttons::default(),
+            read_index: 0,
+            source,
+        }
     }
 
     // Refreshes the buttons when strobe is high. This should be called every clock cycle.
     pub fn clock_pulse(&mut self, ppu: &Ppu) {
         if self.strobe {
-            self.buttons = ppu.get_joypad_state();
+            self.buttons = self.source.buttons(ppu);
             self.read_index = 0;
         }
     }
+}
 
-    // Returns the current button's value
+impl InputDevice for Controller {
+    // Writes to the controller input.
     //
-    // Should be mapped to address $4016 for controller 1 and $4017 for controller 2
-    pub fn read(&mut self, ppu: &Ppu) -> u8 {
+    // Only the least significant bit of the input will be used.
+    fn write(&mut self, byte: u8, ppu: &Ppu) {
+        self.strobe = (byte & 0b1) == 1;
+        self.clock_pulse(ppu);
+    }
+
+    // Returns the current button's value
+    fn read(&mut self, ppu: &Ppu) -> u8 {
         let result = u8::from(match self.read_index {
             0 => self.buttons.a,
             1 => self.buttons.b,
@@ -54,11 +67,20 @@ impl Controller {
         result
     }
 
-    pub fn new() -> Self {
-        Controller {
-            strobe: false,
-            buttons: Buttons::default(),
-            read_index: 0,
-        }
+    fn start_frame(&mut self, frame: u64) {
+        self.source.start_frame(frame);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u8(button_bits(self.buttons));
+        state.write_u8(self.read_index);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.strobe = state.read_bool()?;
+        self.buttons = buttons_from_bits(state.read_u8()?);
+        self.read_index = state.read_u8()? & 0b111;
+        Ok(())
     }
 }
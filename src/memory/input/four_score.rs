@@ -0,0 +1,82 @@
+use super::{button_bits, InputDevice, InputSource, Port};
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Ppu;
+
+// One side of the Four Score multitap, which connects two controllers to each port
+//
+// After a strobe the port shifts out the 8 buttons of its first controller (player 1 or 2), then
+// those of its second controller (player 3 or 4) and then an 8 bit signature that tells games a
+// Four Score is attached. Every read after that returns 1.
+// https://www.nesdev.org/wiki/Four_Score
+#[derive(Debug)]
+pub struct FourScore {
+    signature: u8, // Shifted out least significant bit first
+    strobe: bool,
+    bits: u32, // The bits that are still to be shifted out
+    reads: u8, // Reads since the strobe was released
+    sources: [Box<dyn InputSource>; 2],
+}
+
+impl FourScore {
+    pub fn new(port: Port, first: Box<dyn InputSource>, second: Box<dyn InputSource>) -> Self {
+        FourScore {
+            signature: match port {
+                Port::One => 0b0000_1000,
+                Port::Two => 0b0000_0100,
+            },
+            strobe: false,
+            bits: 0,
+            reads: 0,
+            sources: [first, second],
+        }
+    }
+
+    fn reload(&mut self, ppu: &Ppu) {
+        let first = button_bits(self.sources[0].buttons(ppu)) as u32;
+        let second = button_bits(self.sources[1].buttons(ppu)) as u32;
+        self.bits = first | (second << 8) | ((self.signature as u32) << 16);
+        self.reads = 0;
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, value: u8, ppu: &Ppu) {
+        self.strobe = value & 1 == 1;
+        if self.strobe {
+            self.reload(ppu);
+        }
+    }
+
+    fn read(&mut self, ppu: &Ppu) -> u8 {
+        if self.strobe {
+            self.reload(ppu);
+        }
+        if self.reads >= 24 {
+            return 1;
+        }
+        let bit = (self.bits & 1) as u8;
+        self.bits >>= 1;
+        self.reads += 1;
+        bit
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        for source in &mut self.sources {
+            source.start_frame(frame);
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u32(self.bits);
+        state.write_u8(self.reads);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.strobe = state.read_bool()?;
+        self.bits = state.read_u32()?;
+        self.reads = state.read_u8()?;
+        Ok(())
+    }
+}
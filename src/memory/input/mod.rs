@@ -0,0 +1,234 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+pub use controller::Controller;
+pub use four_score::FourScore;
+pub(crate) use script::{format_fm2_pad, parse_fm2_frame};
+pub use script::{InputScript, ScriptedInput};
+use std::fmt::Debug;
+use std::str::FromStr;
+use tudelft_nes_ppu::{Buttons, Ppu};
+pub use vaus::Vaus;
+pub use zapper::Zapper;
+
+mod controller;
+mod four_score;
+mod script;
+mod vaus;
+mod zapper;
+
+// The two controller ports, read through $4016 and $4017
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Port {
+    One,
+    Two,
+}
+
+// A device plugged into one of the controller ports
+//
+// Writes to $4016 go to the devices in both ports, a read of $4016 or $4017 goes to the device
+// in the first or second port. A device drives data lines D0-D4 of a read, the other bits are
+// left 0.
+// https://www.nesdev.org/wiki/Input_devices
+pub trait InputDevice: Send + Debug {
+    // Handles a write to $4016, of which bit 0 is the strobe line shared by both ports
+    fn write(&mut self, value: u8, ppu: &Ppu);
+
+    // Handles a read of the port
+    fn read(&mut self, ppu: &Ppu) -> u8;
+
+    // Called when the PPU starts drawing a frame, devices pass it on to their sources
+    fn start_frame(&mut self, frame: u64);
+
+    // Appends the state of the device, like its shift register, to a save state
+    //
+    // The sources are not part of it, a state has to be loaded with the same devices plugged in.
+    fn save_state(&self, state: &mut StateWriter);
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+// Provides the buttons held on a controller, asked for whenever the device samples its input
+//
+// Devices that aren't pads map their inputs onto the buttons, see `Zapper` and `Vaus`.
+pub trait InputSource: Send + Debug {
+    fn buttons(&mut self, ppu: &Ppu) -> Buttons;
+
+    // Called when the PPU starts drawing a frame, the first frame is 0
+    fn start_frame(&mut self, _frame: u64) {}
+}
+
+// The buttons pressed in the window of the PPU
+#[derive(Debug, Default)]
+pub struct PpuJoypad;
+
+impl InputSource for PpuJoypad {
+    fn buttons(&mut self, ppu: &Ppu) -> Buttons {
+        ppu.get_joypad_state()
+    }
+}
+
+// A fixed set of buttons, `Buttons::default()` is a controller without any buttons pressed
+impl InputSource for Buttons {
+    fn buttons(&mut self, _ppu: &Ppu) -> Buttons {
+        *self
+    }
+}
+
+// An empty port
+#[derive(Debug, Default)]
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+    fn write(&mut self, _value: u8, _ppu: &Ppu) {}
+
+    fn read(&mut self, _ppu: &Ppu) -> u8 {
+        0
+    }
+
+    fn start_frame(&mut self, _frame: u64) {}
+
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+// The devices that can be chosen for a port on the command line
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum DeviceKind {
+    Pad,
+    Zapper,
+    FourScore,
+    Vaus,
+    Unplugged,
+}
+
+impl DeviceKind {
+    // Creates the device, reading its buttons from the source
+    //
+    // A Four Score gets the source for its first controller, the other one has no buttons pressed.
+    pub fn create(self, port: Port, source: Box<dyn InputSource>) -> Box<dyn InputDevice> {
+        match self {
+            DeviceKind::Pad => Box::new(Controller::new(source)),
+            DeviceKind::Zapper => Box::new(Zapper::new(source)),
+            DeviceKind::FourScore => {
+                Box::new(FourScore::new(port, source, Box::new(Buttons::default())))
+            }
+            DeviceKind::Vaus => Box::new(Vaus::new(source)),
+            DeviceKind::Unplugged => Box::new(Unplugged),
+        }
+    }
+}
+
+impl FromStr for DeviceKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pad" => Ok(DeviceKind::Pad),
+            "zapper" => Ok(DeviceKind::Zapper),
+            "four-score" => Ok(DeviceKind::FourScore),
+            "vaus" => Ok(DeviceKind::Vaus),
+            "none" => Ok(DeviceKind::Unplugged),
+            _ => Err(format!(
+                "Unknown input device: {} (expected pad, zapper, four-score, vaus or none)",
+                name
+            )),
+        }
+    }
+}
+
+// Packs the buttons in the order a pad shifts them out: A, B, Select, Start, Up, Down, Left, Right
+fn button_bits(buttons: Buttons) -> u8 {
+    (0..8).fold(0, |bits, index| {
+        bits | (u8::from(buttons.get_by_index(index)) << index)
+    })
+}
+
+// Unpacks buttons packed by `button_bits`
+fn buttons_from_bits(bits: u8) -> Buttons {
+    let pressed = |index: u8| bits & (1 << index) != 0;
+    Buttons {
+        a: pressed(0),
+        b: pressed(1),
+        select: pressed(2),
+        start: pressed(3),
+        up: pressed(4),
+        down: pressed(5),
+        left: pressed(6),
+        right: pressed(7),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tudelft_nes_ppu::Mirroring;
+
+    fn read_bits(device: &mut dyn InputDevice, ppu: &Ppu, count: usize) -> Vec<u8> {
+        (0..count).map(|_| device.read(ppu)).collect()
+    }
+
+    #[test]
+    fn test_four_score_shifts_two_pads_and_signature() {
+        let ppu = Ppu::new(Mirroring::Horizontal);
+        let player_2 = Buttons {
+            a: true,
+            ..Buttons::default()
+        };
+        let player_4 = Buttons {
+            right: true,
+            ..Buttons::default()
+        };
+        let mut four_score = FourScore::new(Port::Two, Box::new(player_2), Box::new(player_4));
+        four_score.write(1, &ppu);
+        four_score.write(0, &ppu);
+        assert_eq!(
+            read_bits(&mut four_score, &ppu, 8),
+            [1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            read_bits(&mut four_score, &ppu, 8),
+            [0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            read_bits(&mut four_score, &ppu, 8),
+            [0, 0, 1, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(four_score.read(&ppu), 1);
+    }
+
+    #[test]
+    fn test_zapper_trigger_and_light() {
+        let ppu = Ppu::new(Mirroring::Horizontal);
+        let mut zapper = Zapper::new(Box::new(Buttons::default()));
+        assert_eq!(zapper.read(&ppu), 0b0_1000);
+
+        let mut zapper = Zapper::new(Box::new(Buttons {
+            a: true,
+            b: true,
+            ..Buttons::default()
+        }));
+        assert_eq!(zapper.read(&ppu), 0b1_0000);
+    }
+
+    #[test]
+    fn test_vaus_shifts_inverted_position() {
+        let ppu = Ppu::new(Mirroring::Horizontal);
+        let mut vaus = Vaus::new(Box::new(Buttons {
+            right: true,
+            a: true,
+            ..Buttons::default()
+        }));
+        vaus.write(1, &ppu);
+        vaus.write(0, &ppu);
+
+        // The knob starts at 170 and turned right once, D3 shifts out !174 from bit 7 down
+        let position = read_bits(&mut vaus, &ppu, 8)
+            .iter()
+            .fold(0, |position, bits| (position << 1) | ((bits >> 3) & 1));
+        assert_eq!(!position, 174);
+        assert_eq!(vaus.read(&ppu) & 0b1_0000, 0b1_0000);
+    }
+}
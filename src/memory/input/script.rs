@@ -0,0 +1,273 @@
+use super::{InputSource, Port};
+use crate::error::ScriptError;
+use std::sync::Arc;
+use tudelft_nes_ppu::{Buttons, Ppu};
+
+// The order of the buttons in an FM2 input log
+const FM2_BUTTONS: &str = "RLDUTSBA";
+
+// The buttons of the first two controllers for every frame of a run
+//
+// A script is either a list of commands, one per line:
+//
+//   # comments start with #
+//   120 start            press Start on frame 120
+//   130+300 right        hold Right for 300 frames, starting at frame 130
+//   200-210 p2 a b       press A and B on the second controller on frames 200 to 210
+//
+// or an FM2 input log, with a line like `|0|....T...|........||` for every frame starting at
+// frame 0. The other lines of an FM2 file, its header, are ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct InputScript {
+    frames: Vec<[Buttons; 2]>,
+}
+
+impl InputScript {
+    // Creates a script from the buttons of both controllers for every frame
+    pub fn from_frames(frames: Vec<[Buttons; 2]>) -> Self {
+        InputScript { frames }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let fm2 = text.lines().any(|line| line.starts_with('|'));
+        let mut script = InputScript::default();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |details: String| ScriptError::InvalidLine(index + 1, details);
+            if fm2 {
+                if line.starts_with('|') {
+                    let (_, pads) = parse_fm2_frame(line).map_err(invalid)?;
+                    script.frames.push(pads);
+                }
+            } else {
+                script.parse_command(line).map_err(invalid)?;
+            }
+        }
+        Ok(script)
+    }
+
+    // Returns the buttons held by a player in a frame, nothing is pressed after the script ends
+    pub fn buttons(&self, frame: u64, port: Port) -> Buttons {
+        let frame = usize::try_from(frame).unwrap_or(usize::MAX);
+        match self.frames.get(frame) {
+            Some(frame) => frame[port as usize],
+            None => Buttons::default(),
+        }
+    }
+
+    // Returns the number of frames the script has input for
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    fn parse_command(&mut self, line: &str) -> Result<(), String> {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let frames = match words.next() {
+            Some(frames) => frames,
+            None => return Ok(()),
+        };
+        let parse_frame = |frame: &str| {
+            frame
+                .parse::<usize>()
+                .map_err(|_| format!("invalid frame number: {}", frame))
+        };
+        let (first, last) = if let Some((first, last)) = frames.split_once('-') {
+            (parse_frame(first)?, parse_frame(last)?)
+        } else if let Some((first, count)) = frames.split_once('+') {
+            let first = parse_frame(first)?;
+            let count = parse_frame(count)?;
+            (first, first + count.max(1) - 1)
+        } else {
+            let frame = parse_frame(frames)?;
+            (frame, frame)
+        };
+        if last < first {
+            return Err(format!("frame range {} ends before it starts", frames));
+        }
+
+        let mut port = Port::One;
+        let mut buttons = Buttons::default();
+        for word in words {
+            match word.to_ascii_lowercase().as_str() {
+                "p1" => port = Port::One,
+                "p2" => port = Port::Two,
+                "a" => buttons.a = true,
+                "b" => buttons.b = true,
+                "select" => buttons.select = true,
+                "start" => buttons.start = true,
+                "up" => buttons.up = true,
+                "down" => buttons.down = true,
+                "left" => buttons.left = true,
+                "right" => buttons.right = true,
+                _ => return Err(format!("unknown button: {}", word)),
+            }
+        }
+
+        if self.frames.len() <= last {
+            self.frames.resize(last + 1, [Buttons::default(); 2]);
+        }
+        for frame in &mut self.frames[first..=last] {
+            let held = &mut frame[port as usize];
+            *held = merge(*held, buttons);
+        }
+        Ok(())
+    }
+}
+
+// Feeds the buttons of one player of a script to a device
+#[derive(Debug)]
+pub struct ScriptedInput {
+    script: Arc<InputScript>,
+    port: Port,
+    frame: u64,
+}
+
+impl ScriptedInput {
+    pub fn new(script: Arc<InputScript>, port: Port) -> Self {
+        ScriptedInput {
+            script,
+            port,
+            frame: 0,
+        }
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn buttons(&mut self, _ppu: &Ppu) -> Buttons {
+        self.script.buttons(self.frame, self.port)
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+}
+
+// Parses a frame of an FM2 input log, `|commands|port 0|port 1|port 2|`, into its commands and
+// two pads
+pub(crate) fn parse_fm2_frame(line: &str) -> Result<(u8, [Buttons; 2]), String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 4 || !fields[0].is_empty() {
+        return Err(format!("invalid FM2 frame: {}", line));
+    }
+    let commands = fields[1]
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid FM2 commands: {}", fields[1]))?;
+    Ok((
+        commands,
+        [parse_fm2_pad(fields[2])?, parse_fm2_pad(fields[3])?],
+    ))
+}
+
+// Formats the buttons of a pad for an FM2 input log
+pub(crate) fn format_fm2_pad(buttons: Buttons) -> String {
+    let pressed = [
+        buttons.right,
+        buttons.left,
+        buttons.down,
+        buttons.up,
+        buttons.start,
+        buttons.select,
+        buttons.b,
+        buttons.a,
+    ];
+    FM2_BUTTONS
+        .chars()
+        .zip(pressed)
+        .map(|(button, pressed)| if pressed { button } else { '.' })
+        .collect()
+}
+
+// Parses the buttons of a pad in an FM2 input log, any character other than a space or a dot
+// marks a pressed button
+fn parse_fm2_pad(field: &str) -> Result<Buttons, String> {
+    if field.is_empty() {
+        return Ok(Buttons::default());
+    }
+    if field.chars().count() != FM2_BUTTONS.len() {
+        return Err(format!("invalid FM2 pad: {}", field));
+    }
+    let mut buttons = Buttons::default();
+    for (button, state) in FM2_BUTTONS.chars().zip(field.chars()) {
+        let pressed = state != '.' && state != ' ';
+        match button {
+            'R' => buttons.right = pressed,
+            'L' => buttons.left = pressed,
+            'D' => buttons.down = pressed,
+            'U' => buttons.up = pressed,
+            'T' => buttons.start = pressed,
+            'S' => buttons.select = pressed,
+            'B' => buttons.b = pressed,
+            _ => buttons.a = pressed,
+        }
+    }
+    Ok(buttons)
+}
+
+fn merge(first: Buttons, second: Buttons) -> Buttons {
+    Buttons {
+        a: first.a || second.a,
+        b: first.b || second.b,
+        up: first.up || second.up,
+        down: first.down || second.down,
+        left: first.left || second.left,
+        right: first.right || second.right,
+        select: first.select || second.select,
+        start: first.start || second.start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let script = InputScript::parse(
+            "# title screen\n\
+             120 start\n\
+             130+300 right # walk\n\
+             200-210 p2 a B\n",
+        )
+        .unwrap();
+        assert_eq!(script.len(), 430);
+        assert!(script.buttons(120, Port::One).start);
+        assert!(!script.buttons(121, Port::One).start);
+        assert!(script.buttons(429, Port::One).right);
+        assert!(!script.buttons(430, Port::One).right);
+        let player_2 = script.buttons(205, Port::Two);
+        assert!(player_2.a && player_2.b && !player_2.right);
+        assert!(script.buttons(205, Port::One).right);
+
+        assert_eq!(
+            InputScript::parse("10 jump"),
+            Err(ScriptError::InvalidLine(
+                1,
+                "unknown button: jump".to_string()
+            ))
+        );
+        assert!(InputScript::parse("\n20-10 a").is_err());
+    }
+
+    #[test]
+    fn test_parse_fm2_log() {
+        let script = InputScript::parse(
+            "version 3\n\
+             romFilename game\n\
+             |0|........|........||\n\
+             |0|....T...|R......A||\n",
+        )
+        .unwrap();
+        assert_eq!(script.len(), 2);
+        assert_eq!(script.buttons(0, Port::One), Buttons::default());
+        assert!(script.buttons(1, Port::One).start);
+        let player_2 = script.buttons(1, Port::Two);
+        assert!(player_2.right && player_2.a && !player_2.left);
+
+        assert!(InputScript::parse("|0|...T|........||").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use super::{InputDevice, InputSource};
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Ppu;
+
+// The range of the knob as Arkanoid sees it, and how far it turns every time it is latched
+const POSITION_MIN: u8 = 98;
+const POSITION_MAX: u8 = 242;
+const POSITION_STEP: u8 = 4;
+
+// The Arkanoid Vaus controller, a paddle with one button
+//
+// A strobe latches the position of the knob, which reads then shift out on D3 inverted and
+// most significant bit first. D4 is the fire button. The knob is turned with Left and Right
+// of the source, A is the fire button.
+// https://www.nesdev.org/wiki/Arkanoid_controller
+#[derive(Debug)]
+pub struct Vaus {
+    strobe: bool,
+    position: u8, // Position of the knob
+    shift: u8,    // The latched position that is shifted out
+    fire: bool,
+    source: Box<dyn InputSource>,
+}
+
+impl Vaus {
+    pub fn new(source: Box<dyn InputSource>) -> Self {
+        let middle = POSITION_MIN + (POSITION_MAX - POSITION_MIN) / 2;
+        Vaus {
+            strobe: false,
+            position: middle,
+            shift: 0,
+            fire: false,
+            source,
+        }
+    }
+}
+
+impl InputDevice for Vaus {
+    fn write(&mut self, value: u8, ppu: &Ppu) {
+        let strobe = value & 1 == 1;
+        if strobe && !self.strobe {
+            let buttons = self.source.buttons(ppu);
+            if buttons.left {
+                self.position = self.position.saturating_sub(POSITION_STEP);
+            }
+            if buttons.right {
+                self.position = self.position.saturating_add(POSITION_STEP);
+            }
+            self.position = self.position.clamp(POSITION_MIN, POSITION_MAX);
+            self.fire = buttons.a;
+        }
+        if strobe {
+            self.shift = !self.position;
+        }
+        self.strobe = strobe;
+    }
+
+    fn read(&mut self, _ppu: &Ppu) -> u8 {
+        let data = (self.shift >> 7) << 3;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        data | u8::from(self.fire) << 4
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        self.source.start_frame(frame);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u8(self.position);
+        state.write_u8(self.shift);
+        state.write_bool(self.fire);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.strobe = state.read_bool()?;
+        self.position = state.read_u8()?.clamp(POSITION_MIN, POSITION_MAX);
+        self.shift = state.read_u8()?;
+        self.fire = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use super::{InputDevice, InputSource};
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Ppu;
+
+// The NES Zapper light gun
+//
+// Reads return the trigger on D4 and the light sensor on D3, which is 0 while the gun sees a
+// bright part of the screen. The PPU does not give access to the picture, so the sensor can't be
+// emulated from the aim of the gun. Instead the source reports both: A pulls the trigger and B
+// means the gun is pointed at a lit target.
+// https://www.nesdev.org/wiki/Zapper
+#[derive(Debug)]
+pub struct Zapper {
+    source: Box<dyn InputSource>,
+}
+
+impl Zapper {
+    pub fn new(source: Box<dyn InputSource>) -> Self {
+        Zapper { source }
+    }
+}
+
+impl InputDevice for Zapper {
+    // The Zapper ignores the strobe
+    fn write(&mut self, _value: u8, _ppu: &Ppu) {}
+
+    fn read(&mut self, ppu: &Ppu) -> u8 {
+        let buttons = self.source.buttons(ppu);
+        let trigger = u8::from(buttons.a) << 4;
+        let no_light = u8::from(!buttons.b) << 3;
+        trigger | no_light
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        self.source.start_frame(frame);
+    }
+
+    // The Zapper has no state of its own, it reports the source whenever it is read
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
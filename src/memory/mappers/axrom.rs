@@ -0,0 +1,60 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Mirroring;
+
+// AxROM, mapper 7, switches 32 KiB PRG banks and selects one of the nametables for single screen
+// mirroring, starting with the lower one
+#[derive(Debug)]
+pub struct AxRom {
+    prg_bank: u8,
+    upper_nametable: bool,
+}
+
+impl AxRom {
+    pub fn new(banks: &mut Banks) -> Self {
+        banks.map_prg(0x8000, 0x8000, 0);
+        AxRom {
+            prg_bank: 0,
+            upper_nametable: false,
+        }
+    }
+}
+
+impl Mapper for AxRom {
+    fn read_cpu(&self, banks: &Banks, address: u16) -> u8 {
+        match address {
+            0x8000.. => banks.read_prg_rom(address),
+            _ => 0, // no PGR RAM
+        }
+    }
+
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        // writes below $8000 are ignored, there is no PGR RAM
+        if address >= 0x8000 {
+            self.prg_bank = value & 0b111;
+            self.upper_nametable = value & 0b10000 != 0;
+            banks.map_prg(0x8000, 0x8000, self.prg_bank as usize); // switch in 32kb blocks
+        }
+        Ok(())
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.upper_nametable {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        })
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank);
+        state.write_bool(self.upper_nametable);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.prg_bank = state.read_u8()?;
+        self.upper_nametable = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+
+// The memory on a cartridge, and the bank tables through which a mapper maps it
+//
+// PRG ROM is mapped in 8 KiB windows at $8000-$FFFF, CHR in 1 KiB windows at $0000-$1FFF and
+// PRG RAM in a single 8 KiB window at $6000-$7FFF.
+// Mappers point the windows to banks of any multiple of those sizes through `map_prg` and
+// `map_chr`. Bank numbers wrap around the size of the memory, so smaller ROMs are mirrored.
+#[derive(Debug, PartialEq)]
+pub struct Banks {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>, // Program RAM, both the volatile and the battery-backed part
+    chr: Vec<u8>,     // The CHR ROM, or the CHR RAM on cartridges without CHR ROM
+    chr_is_ram: bool,
+    prg_table: [usize; 4], // Offsets into the PRG ROM of the 8 KiB windows at $8000-$FFFF
+    chr_table: [usize; 8], // Offsets into the CHR of the 1 KiB windows at $0000-$1FFF
+    prg_ram_offset: usize, // Offset into the PRG RAM of the window at $6000-$7FFF
+}
+
+impl Banks {
+    // Creates the memory of a cartridge, with CHR RAM of the given size when there is no CHR ROM
+    pub fn new(prg_rom: Vec<u8>, prg_ram: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        let chr_is_ram = chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; chr_ram_size]
+        } else {
+            chr_rom
+        };
+        let mut banks = Banks {
+            prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            prg_table: [0; 4],
+            chr_table: [0; 8],
+            prg_ram_offset: 0,
+        };
+        banks.map_prg(0x8000, 0x8000, 0);
+        banks.map_chr(0x0000, 0x2000, 0);
+        banks
+    }
+
+    // Points the PRG ROM windows at `address`, covering `size` bytes, to a bank of that size
+    pub fn map_prg(&mut self, address: u16, size: usize, bank: usize) {
+        let len = self.prg_rom.len();
+        let start = (bank % (len / size).max(1)) * size;
+        for offset in (0..size).step_by(0x2000) {
+            self.prg_table[(address as usize - 0x8000 + offset) >> 13] = (start + offset) % len;
+        }
+    }
+
+    // Points the CHR windows at `address`, covering `size` bytes, to a bank of that size
+    pub fn map_chr(&mut self, address: u16, size: usize, bank: usize) {
+        let len = self.chr.len().max(1);
+        let start = (bank % (len / size).max(1)) * size;
+        for offset in (0..size).step_by(0x400) {
+            self.chr_table[(address as usize + offset) >> 10] = (start + offset) % len;
+        }
+    }
+
+    // Points the PRG RAM window to an 8 KiB bank
+    pub fn map_prg_ram(&mut self, bank: usize) {
+        self.prg_ram_offset = (bank % (self.prg_ram.len() / 0x2000).max(1)) * 0x2000;
+    }
+
+    // Returns the number of the last PRG ROM bank of the given size
+    pub fn last_prg_bank(&self, size: usize) -> usize {
+        (self.prg_rom.len() / size).max(1) - 1
+    }
+
+    pub fn chr_is_ram(&self) -> bool {
+        self.chr_is_ram
+    }
+
+    // Reads the PRG ROM at $8000-$FFFF
+    pub fn read_prg_rom(&self, address: u16) -> u8 {
+        self.prg_rom[self.prg_rom_offset(address)]
+    }
+
+    pub fn write_prg_rom(&mut self, address: u16, value: u8) {
+        let offset = self.prg_rom_offset(address);
+        self.prg_rom[offset] = value;
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> usize {
+        let offset =
+            self.prg_table[(address as usize & 0x7FFF) >> 13] + (address as usize & 0x1FFF);
+        offset % self.prg_rom.len()
+    }
+
+    // Reads the PRG RAM at $6000-$7FFF, which is mirrored when it is smaller than 8 KiB
+    pub fn read_prg_ram(&self, address: u16) -> u8 {
+        match self.prg_ram.len() {
+            0 => 0, // no PRG RAM
+            len => self.prg_ram[(self.prg_ram_offset + (address as usize & 0x1FFF)) % len],
+        }
+    }
+
+    pub fn write_prg_ram(&mut self, address: u16, value: u8) {
+        let len = self.prg_ram.len();
+        if len != 0 {
+            self.prg_ram[(self.prg_ram_offset + (address as usize & 0x1FFF)) % len] = value;
+        }
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    // Reads the pattern tables at $0000-$1FFF
+    pub fn read_chr(&self, address: u16) -> u8 {
+        match self.chr_offset(address) {
+            Some(offset) => self.chr[offset],
+            None => 0, // no CHR memory
+        }
+    }
+
+    pub fn write_chr(&mut self, address: u16, value: u8) {
+        if let Some(offset) = self.chr_offset(address) {
+            self.chr[offset] = value;
+        }
+    }
+
+    fn chr_offset(&self, address: u16) -> Option<usize> {
+        if self.chr.is_empty() {
+            return None;
+        }
+        let offset = self.chr_table[(address as usize & 0x1FFF) >> 10] + (address as usize & 0x3FF);
+        Some(offset % self.chr.len())
+    }
+
+    // Saves the RAM and the bank tables, the ROM is not part of a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.write_bytes(&self.chr);
+        }
+        for offset in self.prg_table.iter().chain(&self.chr_table) {
+            state.write_u32(*offset as u32);
+        }
+        state.write_u32(self.prg_ram_offset as u32);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.read_bytes_into(&mut self.chr)?;
+        }
+        for offset in self.prg_table.iter_mut() {
+            *offset = state.read_u32()? as usize % self.prg_rom.len();
+        }
+        let chr_len = self.chr.len().max(1);
+        for offset in self.chr_table.iter_mut() {
+            *offset = state.read_u32()? as usize % chr_len;
+        }
+        self.prg_ram_offset = state.read_u32()? as usize % self.prg_ram.len().max(1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banks_wrap_and_mirror() {
+        let prg_rom = (0..4).flat_map(|bank| [bank; 0x2000]).collect();
+        let chr_rom = (0..8).flat_map(|bank| [bank; 0x400]).collect();
+        let mut banks = Banks::new(prg_rom, vec![], chr_rom, 0);
+        assert_eq!(banks.read_prg_rom(0xE000), 3);
+
+        banks.map_prg(0x8000, 0x4000, 3);
+        assert_eq!(banks.read_prg_rom(0x8000), 2);
+        assert_eq!(banks.read_prg_rom(0xA000), 3);
+
+        banks.map_chr(0x1C00, 0x400, 9);
+        assert_eq!(banks.read_chr(0x1C00), 1);
+        assert_eq!(banks.read_prg_ram(0x6000), 0);
+    }
+}
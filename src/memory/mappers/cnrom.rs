@@ -0,0 +1,40 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+// CNROM, mapper 3, switches the whole pattern table in 8 KiB banks
+#[derive(Debug)]
+pub struct CnRom {
+    chr_bank: u8,
+}
+
+impl CnRom {
+    pub fn new(banks: &mut Banks) -> Self {
+        banks.map_chr(0x0000, 0x2000, 0);
+        CnRom { chr_bank: 0 }
+    }
+}
+
+impl Mapper for CnRom {
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        match address {
+            0x6000..0x8000 => banks.write_prg_ram(address, value), // PGR RAM
+            0x8000.. => {
+                // select the chr bank
+                self.chr_bank = value;
+                banks.map_chr(0x0000, 0x2000, value as usize);
+            }
+            _ => {} // open bus
+        }
+        Ok(())
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.chr_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.chr_bank = state.read_u8()?;
+        Ok(())
+    }
+}
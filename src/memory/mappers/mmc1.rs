@@ -0,0 +1,240 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Mirroring;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ProgramBankMode {
+    Fullswitch,
+    Fixfirst,
+    Fixlast,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum CharacterBankMode {
+    Fullswitch,
+    Halfswitch,
+}
+
+// The MMC1 boards that use bits of the CHR bank registers for more than CHR banking
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Board {
+    Standard, // SAROM, SBROM, SKROM, SLROM and the other boards with up to 256 KiB PRG ROM
+    Serom,    // SEROM, SHROM and SH1ROM, which can't switch their 32 KiB of PRG ROM
+    Snrom,    // CHR RAM, bit 4 disables the PRG RAM
+    Sorom,    // 16 KiB PRG RAM, bit 3 selects the PRG RAM bank
+    Surom,    // 512 KiB PRG ROM, bit 4 selects the 256 KiB half
+    Sxrom,    // 512 KiB PRG ROM and 32 KiB PRG RAM, bits 2-3 select the PRG RAM bank
+}
+
+impl Board {
+    // Tells the boards apart from the memory sizes, and the NES 2.0 submapper for SEROM
+    //
+    // Submappers 1-3 used to mark SUROM, SOROM and SXROM, but they are deprecated as the sizes
+    // already identify those boards.
+    pub fn detect(submapper: u8, prg_rom_size: usize, prg_ram_size: usize, chr_ram: bool) -> Self {
+        match (submapper, prg_rom_size, prg_ram_size) {
+            (5, _, _) => Board::Serom,
+            (_, _, 0x8000..) => Board::Sxrom,
+            (_, 0x40001.., _) => Board::Surom,
+            (_, _, 0x4000..) => Board::Sorom,
+            _ if chr_ram => Board::Snrom,
+            _ => Board::Standard,
+        }
+    }
+}
+
+// MMC1, mapper 1
+//
+// The registers are written one bit at a time through a shift register, writing a value with
+// bit 7 set resets it. Writes on consecutive CPU cycles are ignored after the first, which
+// games use to reset the mapper with a read-modify-write instruction.
+// https://www.nesdev.org/wiki/MMC1
+#[derive(Debug)]
+pub struct Mmc1 {
+    board: Board,
+    shift_register: u8, // Bits shifted in so far, the marker bit reaches bit 0 when it is full
+    control: u8, // Mirroring in bits 0-1, PRG bank mode in bits 2-3 and CHR bank mode in bit 4
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,           // PRG bank in bits 0-3, bit 4 disables the PRG RAM
+    cycles_since_write: u8, // CPU cycles since the last write to the registers
+}
+
+impl Mmc1 {
+    pub fn new(board: Board, mirroring: Mirroring, banks: &mut Banks) -> Self {
+        // start with the mirroring of the header and the last bank fixed at 0xc000
+        let mirroring_bits = if mirroring == Mirroring::Vertical {
+            2
+        } else {
+            3
+        };
+        let mmc1 = Mmc1 {
+            board,
+            shift_register: 16,
+            control: 0b01100 | mirroring_bits,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycles_since_write: u8::MAX,
+        };
+        mmc1.update_banks(banks);
+        mmc1
+    }
+
+    fn prg_bank_mode(&self) -> ProgramBankMode {
+        match (self.control >> 2) & 3 {
+            0 | 1 => ProgramBankMode::Fullswitch,
+            2 => ProgramBankMode::Fixfirst,
+            _ => ProgramBankMode::Fixlast,
+        }
+    }
+
+    fn chr_bank_mode(&self) -> CharacterBankMode {
+        if (self.control >> 4) & 1 == 0 {
+            CharacterBankMode::Fullswitch
+        } else {
+            CharacterBankMode::Halfswitch
+        }
+    }
+
+    // Returns true when the PRG RAM can be accessed
+    fn prg_ram_enabled(&self) -> bool {
+        // the upper CHR bits are read from the first CHR register, on the hardware it is the one
+        // in use for the pattern table the PPU last read from, which games keep the same
+        let snrom_disabled = self.board == Board::Snrom && self.chr_bank_0 & 0x10 != 0;
+        self.prg_bank & 0x10 == 0 && !snrom_disabled
+    }
+
+    fn update_banks(&self, banks: &mut Banks) {
+        // SUROM and SXROM select the 256kb half of the PRG ROM with bit 4 of the CHR register
+        let outer = match self.board {
+            Board::Surom | Board::Sxrom => (self.chr_bank_0 & 0x10) as usize,
+            _ => 0,
+        };
+        let banknr = outer | (self.prg_bank & 0x0F) as usize;
+        let last = outer | (banks.last_prg_bank(0x4000) & 0x0F);
+        match self.prg_bank_mode() {
+            _ if self.board == Board::Serom => banks.map_prg(0x8000, 0x8000, 0),
+            // switch in 32kb blocks
+            ProgramBankMode::Fullswitch => banks.map_prg(0x8000, 0x8000, banknr >> 1),
+            ProgramBankMode::Fixfirst => {
+                banks.map_prg(0x8000, 0x4000, outer);
+                banks.map_prg(0xC000, 0x4000, banknr);
+            }
+            ProgramBankMode::Fixlast => {
+                banks.map_prg(0x8000, 0x4000, banknr);
+                banks.map_prg(0xC000, 0x4000, last);
+            }
+        }
+
+        match self.board {
+            Board::Sorom => banks.map_prg_ram((self.chr_bank_0 as usize >> 3) & 1),
+            Board::Sxrom => banks.map_prg_ram((self.chr_bank_0 as usize >> 2) & 3),
+            _ => banks.map_prg_ram(0),
+        }
+
+        // CHR RAM is banked the same way as CHR ROM
+        if self.chr_bank_mode() == CharacterBankMode::Fullswitch {
+            banks.map_chr(0x0000, 0x2000, self.chr_bank_0 as usize >> 1);
+        } else {
+            banks.map_chr(0x0000, 0x1000, self.chr_bank_0 as usize);
+            banks.map_chr(0x1000, 0x1000, self.chr_bank_1 as usize);
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_cpu(&self, banks: &Banks, address: u16) -> u8 {
+        match address {
+            0x6000..0x8000 if self.prg_ram_enabled() => banks.read_prg_ram(address), // PGR RAM
+            0x8000.. => banks.read_prg_rom(address),
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        match address {
+            ..0x6000 => return Ok(()), // open bus
+            0x6000..0x8000 => {
+                if self.prg_ram_enabled() {
+                    banks.write_prg_ram(address, value); // PGR RAM
+                }
+                return Ok(());
+            }
+            0x8000.. => {}
+        }
+
+        // the serial port ignores a write on the cycle after another write
+        let consecutive = self.cycles_since_write <= 1;
+        self.cycles_since_write = 0;
+        if consecutive {
+            return Ok(());
+        }
+
+        // check if the given command could be a reset for the mmc1 mapper
+        if (value & 0b10000000) == 128 {
+            self.shift_register = 16;
+            self.control |= 0b01100;
+            // check if the shift register is full or not
+        } else if (self.shift_register & 1) != 1 {
+            self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
+        } else {
+            // if the shift register is full shift in the fifth bit and write it to the appropriate address
+            self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
+            match address {
+                0x8000..0xa000 => {
+                    log::debug!("editing control register to {:08b}", self.shift_register);
+                    self.control = self.shift_register;
+                }
+                0xa000..0xc000 => {
+                    log::debug!("editing chr0 register to {:08b}", self.shift_register);
+                    self.chr_bank_0 = self.shift_register;
+                }
+                0xc000..0xe000 => {
+                    log::debug!("editing chr1 register to {:08b}", self.shift_register);
+                    self.chr_bank_1 = self.shift_register;
+                }
+                _ => {
+                    log::debug!("editing prg register to {:08b}", self.shift_register);
+                    self.prg_bank = self.shift_register;
+                }
+            }
+            self.shift_register = 16;
+        }
+        self.update_banks(banks);
+        Ok(())
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 3 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.shift_register);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+        state.write_u8(self.cycles_since_write);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.shift_register = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        self.cycles_since_write = state.read_u8()?;
+        Ok(())
+    }
+}
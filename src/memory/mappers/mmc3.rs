@@ -0,0 +1,160 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::Mirroring;
+
+// MMC3, mapper 4, with a scanline counter that can interrupt the CPU
+//
+// Registers are selected by the address range and whether the address is even or odd.
+// https://www.nesdev.org/wiki/MMC3
+#[derive(Debug)]
+pub struct Mmc3 {
+    bank_select: u8,         // Bank select register, including the inversion modes
+    bank_registers: [u8; 8], // Bank registers R0-R7
+    horizontal: bool,        // Horizontal mirroring instead of vertical
+    prg_ram_enabled: bool,   // PRG RAM chip enable
+    prg_ram_protected: bool, // PRG RAM write protection
+    irq_latch: u8,           // Value the scanline counter is reloaded with
+    irq_counter: u8,         // Scanline counter
+    irq_reload: bool,        // Reload the scanline counter on the next clock
+    irq_enabled: bool,
+    irq_flag: bool, // Set while the mapper asserts the IRQ line
+}
+
+impl Mmc3 {
+    pub fn new(mirroring: Mirroring, banks: &mut Banks) -> Self {
+        let mmc3 = Mmc3 {
+            bank_select: 0,
+            bank_registers: [0; 8],
+            horizontal: mirroring == Mirroring::Horizontal,
+            prg_ram_enabled: true,
+            prg_ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_flag: false,
+        };
+        mmc3.update_banks(banks);
+        mmc3
+    }
+
+    fn update_banks(&self, banks: &mut Banks) {
+        let last = banks.last_prg_bank(0x2000);
+        let second_last = last.saturating_sub(1);
+        // the PRG mode bit swaps the banks at 0x8000 and 0xc000
+        let (bank_8000, bank_c000) = if self.bank_select & 0x40 == 0 {
+            (self.bank_registers[6] as usize, second_last)
+        } else {
+            (second_last, self.bank_registers[6] as usize)
+        };
+        banks.map_prg(0x8000, 0x2000, bank_8000);
+        banks.map_prg(0xA000, 0x2000, self.bank_registers[7] as usize);
+        banks.map_prg(0xC000, 0x2000, bank_c000);
+        banks.map_prg(0xE000, 0x2000, last);
+
+        // two 2kb and four 1kb CHR banks, the inversion bit swaps the halves
+        let (two_kb, one_kb) = if self.bank_select & 0x80 == 0 {
+            (0x0000, 0x1000)
+        } else {
+            (0x1000, 0x0000)
+        };
+        banks.map_chr(two_kb, 0x800, self.bank_registers[0] as usize >> 1);
+        banks.map_chr(two_kb + 0x800, 0x800, self.bank_registers[1] as usize >> 1);
+        for i in 0..4 {
+            let bank = self.bank_registers[2 + i] as usize;
+            banks.map_chr(one_kb + i as u16 * 0x400, 0x400, bank);
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_cpu(&self, banks: &Banks, address: u16) -> u8 {
+        match address {
+            0x6000..0x8000 if self.prg_ram_enabled => banks.read_prg_ram(address), // PGR RAM
+            0x8000.. => banks.read_prg_rom(address),
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        match (address, address & 1) {
+            (0x6000..0x8000, _) if self.prg_ram_enabled && !self.prg_ram_protected => {
+                banks.write_prg_ram(address, value); // PGR RAM
+            }
+            (0x8000..0xa000, 0) => self.bank_select = value,
+            (0x8000..0xa000, _) => self.bank_registers[(self.bank_select & 7) as usize] = value,
+            (0xa000..0xc000, 0) => self.horizontal = value & 1 != 0,
+            (0xa000..0xc000, _) => {
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.prg_ram_protected = value & 0x40 != 0;
+            }
+            (0xc000..0xe000, 0) => self.irq_latch = value,
+            (0xc000..0xe000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // disabling the interrupt also acknowledges it
+            (0xe000.., 0) => {
+                self.irq_enabled = false;
+                self.irq_flag = false;
+            }
+            (0xe000.., _) => self.irq_enabled = true,
+            _ => {} // open bus, or disabled or protected PGR RAM
+        }
+        self.update_banks(banks);
+        Ok(())
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        })
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    // Clocks the scanline counter
+    fn a12_rising(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_flag = true;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.bank_select);
+        state.write_bytes(&self.bank_registers);
+        state.write_bool(self.horizontal);
+        state.write_bool(self.prg_ram_enabled);
+        state.write_bool(self.prg_ram_protected);
+        state.write_u8(self.irq_latch);
+        state.write_u8(self.irq_counter);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_flag);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.bank_select = state.read_u8()?;
+        state.read_bytes_into(&mut self.bank_registers)?;
+        self.horizontal = state.read_bool()?;
+        self.prg_ram_enabled = state.read_bool()?;
+        self.prg_ram_protected = state.read_bool()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_flag = state.read_bool()?;
+        Ok(())
+    }
+}
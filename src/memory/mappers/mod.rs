@@ -0,0 +1,95 @@
+use crate::error::{MemoryError, RomError, SaveStateError};
+use crate::memory::RomHeader;
+use crate::savestate::{StateReader, StateWriter};
+pub use banks::Banks;
+use std::fmt::Debug;
+use tudelft_nes_ppu::Mirroring;
+
+mod axrom;
+mod banks;
+mod cnrom;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod uxrom;
+
+// The logic of a cartridge board, which maps the memory of the cartridge into the address spaces
+// of the CPU and the PPU
+//
+// A mapper only holds its registers. The memory itself is kept in `Banks`, which is handed to
+// every call, and the mapper switches banks by updating its bank tables.
+pub trait Mapper: Send + Debug {
+    // Handles a CPU read from $6000-$FFFF
+    fn read_cpu(&self, banks: &Banks, address: u16) -> u8 {
+        match address {
+            0x6000..0x8000 => banks.read_prg_ram(address),
+            0x8000.. => banks.read_prg_rom(address),
+            _ => 0,
+        }
+    }
+
+    // Handles a CPU write to $4020-$FFFF
+    //
+    // Writes to addresses the board does not connect, like the expansion area below $6000, are
+    // ignored. An error stops the emulator.
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError>;
+
+    // Handles a PPU read from the pattern tables at $0000-$1FFF
+    fn read_ppu(&self, banks: &Banks, address: u16) -> u8 {
+        banks.read_chr(address)
+    }
+
+    // Handles a PPU write to the pattern tables at $0000-$1FFF
+    fn write_ppu(&mut self, banks: &mut Banks, address: u16, value: u8) {
+        banks.write_chr(address, value);
+    }
+
+    // Returns the nametable mirroring selected by the mapper, or None when it is fixed by the board
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // Returns true while the mapper asserts the IRQ line of the CPU
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Called once every CPU cycle
+    fn cpu_cycle(&mut self) {}
+
+    // Called on every rising edge of PPU address line A12, see `Memory::tick_ppu_timing`
+    fn a12_rising(&mut self) {}
+
+    // Appends the registers of the mapper to a save state
+    fn save_state(&self, state: &mut StateWriter);
+
+    // Restores the registers written by `save_state`, the bank tables are restored by `Banks`
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+// Creates the mapper with the mapper number of the header
+pub fn new_mapper(header: &RomHeader, banks: &mut Banks) -> Result<Box<dyn Mapper>, RomError> {
+    let mirroring = header.mirroring;
+    Ok(match header.mapper_number {
+        0 => Box::new(nrom::Nrom),
+        1 => {
+            let board = mmc1::Board::detect(
+                header.submapper,
+                header.program_rom_size,
+                banks.prg_ram().len(),
+                banks.chr_is_ram(),
+            );
+            Box::new(mmc1::Mmc1::new(board, mirroring, banks))
+        }
+        2 => Box::new(uxrom::UxRom::new(banks)),
+        3 => Box::new(cnrom::CnRom::new(banks)),
+        4 => Box::new(mmc3::Mmc3::new(mirroring, banks)),
+        7 => Box::new(axrom::AxRom::new(banks)),
+        mapper_number => {
+            return Err(RomError::UnknownMapper(
+                mapper_number,
+                "Mapper not supported".to_string(),
+            ))
+        }
+    })
+}
@@ -0,0 +1,26 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+// NROM, mapper 0, has no bank switching
+//
+// NROM-128 has 16 KiB of PRG ROM, which the bank table mirrors through $8000-$FFFF.
+#[derive(Debug)]
+pub struct Nrom;
+
+impl Mapper for Nrom {
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        match address {
+            0x6000..0x8000 => banks.write_prg_ram(address, value), // PGR RAM
+            0x8000.. => banks.write_prg_rom(address, value),       // prg rom
+            _ => {}                                                // open bus
+        }
+        Ok(())
+    }
+
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
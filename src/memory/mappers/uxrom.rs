@@ -0,0 +1,46 @@
+use crate::error::{MemoryError, SaveStateError};
+use crate::memory::mappers::{Banks, Mapper};
+use crate::savestate::{StateReader, StateWriter};
+
+// UxROM, mapper 2, switches a 16 KiB bank at $8000 and fixes the last bank at $C000
+#[derive(Debug)]
+pub struct UxRom {
+    prg_bank: u8,
+}
+
+impl UxRom {
+    pub fn new(banks: &mut Banks) -> Self {
+        let uxrom = UxRom { prg_bank: 0 };
+        uxrom.update_banks(banks);
+        uxrom
+    }
+
+    fn update_banks(&self, banks: &mut Banks) {
+        banks.map_prg(0x8000, 0x4000, self.prg_bank as usize);
+        banks.map_prg(0xC000, 0x4000, banks.last_prg_bank(0x4000));
+    }
+}
+
+impl Mapper for UxRom {
+    fn write_cpu(&mut self, banks: &mut Banks, address: u16, value: u8) -> Result<(), MemoryError> {
+        match address {
+            0x6000..0x8000 => banks.write_prg_ram(address, value), // PGR RAM
+            0x8000.. => {
+                // select the bank at 0x8000
+                self.prg_bank = value;
+                self.update_banks(banks);
+            }
+            _ => {} // open bus
+        }
+        Ok(())
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.prg_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.prg_bank = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -1,11 +1,30 @@
+use crate::apu::Apu;
+use crate::audio::AudioOutput;
+use crate::checksum::md5;
 use crate::cpu::Cpu;
-use crate::error::{MemoryError, RomError};
-use controller::Controller;
+use crate::error::{MemoryError, RomError, SaveStateError};
+use crate::movie::{MovieRecorder, COMMAND_RESET};
+use crate::savestate::{StateReader, StateWriter};
+use battery::BatteryFile;
+use input::{Controller, InputDevice, Port, PpuJoypad, Unplugged};
 use log::warn;
-use std::cell::RefCell;
+use mappers::{Banks, Mapper};
+use nametables::NametableMirror;
+use observer::{Access, AccessKind, AccessSource, MemoryObserver};
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::path::PathBuf;
 use tudelft_nes_ppu::{Mirroring, Ppu, PpuRegister};
 
-mod controller;
+mod battery;
+pub mod input;
+mod mappers;
+mod nametables;
+pub mod observer;
+
+// Dots in a scanline and in a frame of the PPU, which does not skip a dot on odd frames
+const DOTS_PER_SCANLINE: u32 = 341;
+const DOTS_PER_FRAME: u32 = DOTS_PER_SCANLINE * 262;
 
 fn address_to_ppu_register(a: u16) -> PpuRegister {
     let reg_num = (a & 0b111) as u8; // Translate address to register number
@@ -25,57 +44,102 @@ fn test_address_to_ppu_register() {
 pub struct Memory {
     internal_ram: [u8; 2048],
     cartridge: Cartridge,
-    controller: RefCell<Controller>,
+    ports: RefCell<[Box<dyn InputDevice>; 2]>, // The devices read through $4016 and $4017
+    apu: RefCell<Apu>,
     ppuaddress: u32,
     oamdata: [u8; 256],
+    last_read_address: Cell<u16>, // Address of the last read done by the CPU
+    audio_output: Option<AudioOutput>,
+    ppu_ctrl: u8,                                       // Last value written to PPUCTRL
+    ppu_mask: u8,                                       // Last value written to PPUMASK
+    ppu_dot: u32,                                       // Position of the PPU in the current frame
+    frame: u64,                                         // Number of frames the PPU has started
+    movie_recorder: Option<MovieRecorder>,              // Records the resets and frames of a movie
+    movie_commands: Vec<u8>,                            // Resets in every frame of a played movie
+    queued_command: u8,                                 // Reset requested for the next frame
+    pending_command: u8,                                // Reset the CPU has to do now
+    ppu_outdated: bool,                                 // Set by loading a state, see `restore_ppu`
+    nametables: RefCell<Option<NametableMirror>>, // Set when the mirroring can change at runtime
+    battery_file: Option<BatteryFile>,            // Save file for battery-backed PRG RAM
+    observer: RefCell<Option<Box<dyn MemoryObserver>>>, // Told about every access, for watchpoints
 }
 
 // A struct for handling memory access for the PPU and CPU
 impl Memory {
     pub fn new(rom_bytes: &[u8]) -> Result<Memory, RomError> {
+        let cartridge = Cartridge::new(rom_bytes)?;
+        let nametables = if cartridge.has_dynamic_mirroring() {
+            Some(NametableMirror::new(cartridge.mirroring()))
+        } else {
+            None
+        };
         Ok(Memory {
-            cartridge: Cartridge::new(rom_bytes)?,
+            cartridge,
             internal_ram: [0; 2048],
-            controller: RefCell::new(Controller::new()),
+            ports: RefCell::new([
+                Box::new(Controller::new(Box::new(PpuJoypad))),
+                Box::new(Unplugged),
+            ]),
+            apu: RefCell::new(Apu::new()),
             ppuaddress: 0,
             oamdata: [0; 256],
+            last_read_address: Cell::new(0),
+            audio_output: None,
+            ppu_ctrl: 0,
+            ppu_mask: 0,
+            ppu_dot: 0,
+            frame: 0,
+            movie_recorder: None,
+            movie_commands: Vec::new(),
+            queued_command: 0,
+            pending_command: 0,
+            ppu_outdated: false,
+            nametables: RefCell::new(nametables),
+            battery_file: None,
+            observer: RefCell::new(None),
         })
     }
 
+    // Keeps the PRG RAM in a save file, when the cartridge has a battery
+    //
+    // The RAM is loaded from the file when it exists, and written back periodically and when
+    // the memory is dropped. Closing the window exits without dropping it, so only the periodic
+    // writes happen then.
+    pub fn set_battery_file(&mut self, path: PathBuf) -> io::Result<()> {
+        if !self.cartridge.header.peristent_memory {
+            return Ok(());
+        }
+        let (file, ram) = BatteryFile::open(path)?;
+        if let Some(ram) = ram {
+            let pgr_ram = self.cartridge.banks.prg_ram_mut();
+            if ram.len() != pgr_ram.len() {
+                warn!(
+                    "save file has {} bytes instead of {}",
+                    ram.len(),
+                    pgr_ram.len()
+                );
+            }
+            let length = ram.len().min(pgr_ram.len());
+            pgr_ram[..length].copy_from_slice(&ram[..length]);
+        }
+        self.battery_file = Some(file);
+        Ok(())
+    }
+
+    pub fn tick_battery(&mut self) {
+        if let Some(file) = &mut self.battery_file {
+            file.tick(self.cartridge.banks.prg_ram());
+        }
+    }
+
     // Writes to the character ROM
     //
     // This funtion should be used when the PPU wants to write to the character ROM
     pub fn write_ppu_byte(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
-        if self.cartridge.header.charactor_memory_size != 0 {
-            if self.cartridge.chr_bank_mode == CharacterBankMode::Fullswitch {
-                let banknr: u32 = self.cartridge.chr_bank_0 as u32 >> 1;
-                let target: u32 = address as u32 + banknr * 0x2000;
-                self.cartridge.chr_data[target as usize] = value;
-            } else {
-                match address {
-                    0x0000..0x1000 => {
-                        let target: u32 =
-                            address as u32 + self.cartridge.chr_bank_0 as u32 * 0x1000;
-                        self.cartridge.chr_data[target as usize] = value;
-                    }
-                    0x1000..0x2000 => {
-                        let target: u32 =
-                            address as u32 + self.cartridge.chr_bank_1 as u32 * 0x1000;
-                        self.cartridge.chr_data[target as usize] = value;
-                    }
-                    _ => {
-                        return Err(MemoryError::UnknownAddress(
-                            "Address out of range - write_ppu_byte".to_string(),
-                        ))
-                    }
-                }
-            }
-        } else {
-            if address > 0x2000 {
-                log::debug!("address too large: {:4X}", address);
-            }
-            self.cartridge.chr_ram[address as usize] = value;
-        }
+        let cartridge = &mut self.cartridge;
+        cartridge
+            .mapper
+            .write_ppu(&mut cartridge.banks, address, value);
         Ok(())
     }
 
@@ -83,33 +147,27 @@ impl Memory {
     //
     // This funtion should be used when the PPU wants to read the character ROM
     pub fn read_ppu_byte(&self, address: u16) -> Result<u8, MemoryError> {
-        if self.cartridge.header.charactor_memory_size != 0 {
-            if self.cartridge.chr_bank_mode == CharacterBankMode::Fullswitch {
-                let banknr: u32 = self.cartridge.chr_bank_0 as u32 >> 1;
-                let target: u32 = address as u32 + banknr * 0x2000;
-                Ok(self.cartridge.chr_data[target as usize])
-            } else {
-                match address {
-                    0x0000..0x1000 => {
-                        let target: u32 =
-                            address as u32 + self.cartridge.chr_bank_0 as u32 * 0x1000;
-                        Ok(self.cartridge.chr_data[target as usize])
-                    }
-                    0x1000..0x2000 => {
-                        let target: u32 =
-                            address as u32 + self.cartridge.chr_bank_1 as u32 * 0x1000;
-                        Ok(self.cartridge.chr_data[target as usize])
-                    }
-                    _ => Err(MemoryError::UnknownAddress(
-                        "Address out of range - read_ppu_byte".to_string(),
-                    )),
-                }
-            }
+        Ok(self
+            .cartridge
+            .mapper
+            .read_ppu(&self.cartridge.banks, address))
+    }
+
+    // Returns the nametable mirroring currently selected by the cartridge
+    pub fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    // Returns the mirroring the PPU has to be created with
+    //
+    // The mirroring of the PPU can't be changed once it is created. When the cartridge can change
+    // the mirroring at runtime, the PPU gets four-screen mirroring and the mirroring is emulated
+    // by `NametableMirror` instead.
+    pub fn ppu_mirroring(&self) -> Mirroring {
+        if self.nametables.borrow().is_some() {
+            Mirroring::FourScreen
         } else {
-            if address > 0x2000 {
-                log::debug!("address too large: {:4X}", address);
-            }
-            Ok(self.cartridge.chr_ram[address as usize])
+            self.mirroring()
         }
     }
 
@@ -118,29 +176,50 @@ impl Memory {
     // This function writes to a part of memory, using the memory map as defined here:
     // https://www.nesdev.org/wiki/CPU_memory_map
     pub fn write(&mut self, address: u16, value: u8, ppu: &mut Ppu) -> Result<(), MemoryError> {
+        self.notify(AccessKind::Write, AccessSource::Cpu, address, value);
         match address {
             ..0x2000 => self.internal_ram[(address & 0x07ff) as usize] = value, // RAM reading, including mirroring
             0x2000..0x4000 => {
                 log::debug!("register written to value: {}", value);
                 let _register = address_to_ppu_register(address);
+                match _register {
+                    PpuRegister::Controller => self.ppu_ctrl = value,
+                    PpuRegister::Mask => self.ppu_mask = value,
+                    _ => {}
+                }
                 ppu.write_ppu_register(_register, value);
+                if let Some(nametables) = self.nametables.get_mut() {
+                    nametables.register_written(_register, value, ppu);
+                }
                 log::debug!("ppu reg address: 0x{:4X}", self.ppuaddress);
                 log::debug!("writing {:?} to: {:?}", value, _register);
             } // NES PPU registers
-            0x4000..0x4014 => {} // TODO: NES APU and I/O registers
+            0x4000..0x4014 => self.apu.get_mut().write_register(address, value), // NES APU registers
             0x4014 => {
                 for i in 0..256 {
-                    self.oamdata[i] = self
-                        .read_cpu_mem(((value as u16) << 8) + i as u16)
-                        .expect("invalid oam read");
+                    let address = ((value as u16) << 8) + i as u16;
+                    self.oamdata[i] = self.read_cpu_mem(address).expect("invalid oam read");
+                    let byte = self.oamdata[i];
+                    self.notify(AccessKind::Read, AccessSource::OamDma, address, byte);
+                    self.notify(AccessKind::Write, AccessSource::OamDma, 0x2004, byte);
                 }
                 log::debug!("writing oam");
                 ppu.write_oam_dma(self.oamdata);
             }
-            0x4015..0x4016 => {}
-            0x4016 => self.controller.borrow_mut().write(value, ppu), // NES APU and I/O registers
-            0x4017..0x4020 => {} // TODO: APU and I/O functionality that is normally disabled
-            0x4020.. => return self.cartridge.write(address, value), // Cartridge memory
+            0x4015 => self.apu.get_mut().write_register(address, value), // APU status register
+            0x4016 => {
+                for device in self.ports.get_mut() {
+                    device.write(value, ppu);
+                }
+            } // Controller strobe
+            0x4017 => self.apu.get_mut().write_register(address, value), // APU frame counter
+            0x4018..0x4020 => {} // APU and I/O functionality that is normally disabled
+            0x4020.. => {
+                self.cartridge.write(address, value)?;
+                if let Some(nametables) = self.nametables.get_mut() {
+                    nametables.set_mirroring(self.cartridge.mirroring(), ppu);
+                }
+            } // Cartridge memory
         };
 
         Ok(())
@@ -151,27 +230,38 @@ impl Memory {
     // This function reads a part of memory, using the memory map as defined here:
     // https://www.nesdev.org/wiki/CPU_memory_map
     pub fn read(&self, address: u16, cpu: &Cpu, ppu: &mut Ppu) -> Result<u8, MemoryError> {
+        self.read_as(AccessSource::Cpu, address, cpu, ppu)
+    }
+
+    fn read_as(
+        &self,
+        source: AccessSource,
+        address: u16,
+        cpu: &Cpu,
+        ppu: &mut Ppu,
+    ) -> Result<u8, MemoryError> {
+        self.last_read_address.set(address);
         let value = match address {
             0x2000..0x4000 => {
                 let register = address_to_ppu_register(address);
-                Ok(ppu.read_ppu_register(register, cpu))
+                let value = ppu.read_ppu_register(register, cpu);
+                if let Some(nametables) = self.nametables.borrow_mut().as_mut() {
+                    nametables.register_read(register);
+                }
+                Ok(value)
             }
-            0x4016 => Ok(self.controller.borrow_mut().read(ppu)),
+            0x4015 => Ok(self.apu.borrow_mut().read_status()),
+            0x4016 => Ok(self.ports.borrow_mut()[0].read(ppu)),
+            0x4017 => Ok(self.ports.borrow_mut()[1].read(ppu)),
             _ => self.read_cpu_mem(address),
         };
-        // Debug printing
-        log::debug!(
-            "Currently in prg bank: {:?}, with mode: {:?}",
-            self.cartridge.prg_bank,
-            self.cartridge.prg_bank_mode
-        );
-        if value.is_ok() {
-            let tmp = value.unwrap();
+        if let Ok(tmp) = value {
             log::debug!(
                 "Read memory byte at address 0x{:04X}: 0x{:02X}",
                 address,
                 tmp
             );
+            self.notify(AccessKind::Read, source, address, tmp);
             return Ok(tmp);
         } else {
             log::debug!("Read memory byte at address 0x{:04X}: FAILED", address);
@@ -179,10 +269,248 @@ impl Memory {
         value
     }
 
+    // Reports every access from now on to an observer
+    pub fn set_observer(&mut self, observer: Box<dyn MemoryObserver>) {
+        *self.observer.get_mut() = Some(observer);
+    }
+
+    fn notify(&self, kind: AccessKind, source: AccessSource, address: u16, value: u8) {
+        if let Some(observer) = self.observer.borrow_mut().as_mut() {
+            observer.access(Access {
+                kind,
+                source,
+                address,
+                value,
+            });
+        }
+    }
+
+    // Advance the APU by one CPU cycle
+    pub fn tick_apu(&mut self) {
+        let apu = self.apu.get_mut();
+        apu.tick();
+        if let Some(output) = &mut self.audio_output {
+            output.push(apu.output());
+        }
+    }
+
+    pub fn set_audio_output(&mut self, output: AudioOutput) {
+        self.audio_output = Some(output);
+    }
+
+    // Returns the number of frames the PPU has started since power-on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Returns the MD5 of the PRG and CHR ROM, which identifies the ROM in movies
+    pub fn rom_checksum(&self) -> [u8; 16] {
+        self.cartridge.checksum
+    }
+
+    pub fn record_movie(&mut self, recorder: MovieRecorder) {
+        self.movie_recorder = Some(recorder);
+    }
+
+    // Requests a reset or power cycle at the start of the next frame, where a movie records it
+    pub fn queue_command(&mut self, command: u8) {
+        self.queued_command |= command;
+    }
+
+    // Plays the resets of a movie, the movie starts at power-on which makes a power cycle in the
+    // first frame redundant
+    pub fn play_movie_commands(&mut self, commands: Vec<u8>) {
+        self.pending_command = commands.first().copied().unwrap_or(0) & COMMAND_RESET;
+        self.movie_commands = commands;
+    }
+
+    // Returns the reset the CPU has to do at the start of this frame, if any
+    pub fn take_command(&mut self) -> u8 {
+        std::mem::take(&mut self.pending_command)
+    }
+
+    // Silences the APU, the rest of the memory keeps its state on a reset
+    pub fn reset(&mut self) {
+        self.apu.get_mut().write_register(0x4015, 0);
+    }
+
+    // Clears the internal RAM and brings the APU and the mapper back to their power-on state
+    //
+    // The PPU keeps its state, as it can't be reset from outside of the PPU crate.
+    pub fn power_cycle(&mut self) {
+        self.internal_ram = [0; 2048];
+        *self.apu.get_mut() = Apu::new();
+        self.cartridge.power_cycle();
+    }
+
+    // Saves the console RAM, the input devices, the APU and the cartridge
+    //
+    // The PPU is not part of a save state, as its state is private to the PPU crate. The position
+    // in the frame is left out as well, it has to keep following the PPU.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.internal_ram);
+        state.write_bytes(&self.oamdata);
+        state.write_u16(self.last_read_address.get());
+        state.write_u8(self.ppu_ctrl);
+        state.write_u8(self.ppu_mask);
+        state.write_u64(self.frame);
+        state.write_u8(self.queued_command);
+        state.write_u8(self.pending_command);
+        for device in self.ports.borrow().iter() {
+            device.save_state(state);
+        }
+        self.apu.borrow().save_state(state);
+        if let Some(nametables) = self.nametables.borrow().as_ref() {
+            nametables.save_state(state);
+        }
+        self.cartridge.save_state(state);
+    }
+
+    // Restores a state written by `save_state`, the PPU is updated by the next `restore_ppu`
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_bytes_into(&mut self.internal_ram)?;
+        state.read_bytes_into(&mut self.oamdata)?;
+        self.last_read_address.set(state.read_u16()?);
+        self.ppu_ctrl = state.read_u8()?;
+        self.ppu_mask = state.read_u8()?;
+        self.frame = state.read_u64()?;
+        self.queued_command = state.read_u8()?;
+        self.pending_command = state.read_u8()?;
+        for device in self.ports.get_mut() {
+            device.load_state(state)?;
+            device.start_frame(self.frame);
+        }
+        self.apu.get_mut().load_state(state)?;
+        if let Some(nametables) = self.nametables.get_mut() {
+            nametables.load_state(state)?;
+        }
+        self.cartridge.load_state(state)?;
+        self.ppu_outdated = true;
+        Ok(())
+    }
+
+    // Brings the PPU as close to a loaded state as its registers allow
+    //
+    // The sprites, PPUCTRL, PPUMASK and, when the nametable mirroring is emulated, the nametables
+    // are written back. The palette, the nametables of boards with fixed mirroring and the
+    // scroll position are only known to the PPU, they stay as they are until the game rewrites
+    // them.
+    pub fn restore_ppu(&mut self, ppu: &mut Ppu) {
+        if !std::mem::take(&mut self.ppu_outdated) {
+            return;
+        }
+        ppu.write_oam_dma(self.oamdata);
+        ppu.write_ppu_register(PpuRegister::Mask, self.ppu_mask);
+        ppu.write_ppu_register(PpuRegister::Controller, self.ppu_ctrl);
+        if let Some(nametables) = self.nametables.get_mut() {
+            nametables.rewrite(ppu);
+        }
+    }
+
+    // Plugs a device into one of the controller ports
+    pub fn set_input_device(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        let index = match port {
+            Port::One => 0,
+            Port::Two => 1,
+        };
+        self.ports.get_mut()[index] = device;
+    }
+
+    // Follows the PPU through the frame, clocks the scanline counter of the mapper and tells the
+    // input devices when a frame starts
+    //
+    // The PPU fetches pattern data for every pixel it draws and only fetches sprite patterns
+    // where sprites are drawn, so a mapper watching address line A12 of the CHR fetches would
+    // not see the edges the hardware produces. Instead the rising edge of A12 is derived from the
+    // position of the PPU: it is ticked three dots after every CPU cycle, starting at the first
+    // dot of a frame, so its position follows from the number of cycles.
+    pub fn tick_ppu_timing(&mut self) {
+        let rendering = self.ppu_mask & 0b0001_1000 != 0;
+        // A12 rises when the sprite patterns are fetched from $1000 (8x16 sprites usually are),
+        // otherwise when the background patterns of the next scanline are fetched from $1000
+        let a12_rise_dot = if self.ppu_ctrl & 0b0010_1000 != 0 {
+            Some(260)
+        } else if self.ppu_ctrl & 0b0001_0000 != 0 {
+            Some(324)
+        } else {
+            None
+        };
+        for _ in 0..3 {
+            let scanline = self.ppu_dot / DOTS_PER_SCANLINE;
+            if rendering
+                && (scanline < 240 || scanline == 261)
+                && Some(self.ppu_dot % DOTS_PER_SCANLINE) == a12_rise_dot
+            {
+                self.cartridge.mapper.a12_rising();
+            }
+            self.ppu_dot = (self.ppu_dot + 1) % DOTS_PER_FRAME;
+            if self.ppu_dot == 0 {
+                self.frame += 1;
+                for device in self.ports.get_mut() {
+                    device.start_frame(self.frame);
+                }
+                let frame = usize::try_from(self.frame).unwrap_or(usize::MAX);
+                let command = std::mem::take(&mut self.queued_command)
+                    | self.movie_commands.get(frame).copied().unwrap_or(0);
+                if let Some(recorder) = &self.movie_recorder {
+                    recorder.record_command(self.frame, command);
+                    if let Err(e) = recorder.start_frame(self.frame) {
+                        log::error!("could not write the movie: {}", e);
+                    }
+                }
+                self.pending_command |= command;
+            }
+        }
+    }
+
+    // Advance the mapper by one CPU cycle
+    pub fn tick_mapper(&mut self) {
+        self.cartridge.mapper.cpu_cycle();
+    }
+
+    // Returns true while the mapper is asserting the IRQ line
+    pub fn mapper_irq(&self) -> bool {
+        self.cartridge.mapper.irq()
+    }
+
+    // Returns true while the APU frame counter is asserting the IRQ line
+    pub fn frame_counter_irq(&self) -> bool {
+        self.apu.borrow().frame_counter_irq()
+    }
+
+    // Returns true while the DMC is asserting the IRQ line
+    pub fn dmc_irq(&self) -> bool {
+        self.apu.borrow().dmc_irq()
+    }
+
+    // Performs the sample fetch the DMC is waiting for, if there is one
+    //
+    // Returns true when a byte was fetched, the CPU should then be stalled. While it is halted
+    // the CPU repeats its last read, which clocks the controller an extra time when that read
+    // was from $4016 or $4017. This is why some games read the controller until two reads agree.
+    pub fn dmc_dma(&self, cpu: &Cpu, ppu: &mut Ppu) -> Result<bool, MemoryError> {
+        let address = match self.apu.borrow().dmc_dma_address() {
+            Some(address) => address,
+            None => return Ok(false),
+        };
+        let last_read_address = self.last_read_address.get();
+        match last_read_address {
+            0x4016 => self.ports.borrow_mut()[0].read(ppu),
+            0x4017 => self.ports.borrow_mut()[1].read(ppu),
+            _ => 0,
+        };
+        let value = self.read_as(AccessSource::DmcDma, address, cpu, ppu)?;
+        self.last_read_address.set(last_read_address);
+        self.apu.borrow_mut().dmc_dma_complete(value);
+        log::debug!("DMC fetched 0x{:02X} from 0x{:04X}", value, address);
+        Ok(true)
+    }
+
     // Reads the parts of memory that don't need access to the PPU
     //
     // This function can only read parts of memory that don't need access to the PPU. This function
-    // is useful for testing, but don't use this in any other part of the code.
+    // is useful for testing and for the debugger, which must not change anything it looks at, but
+    // don't use this in any other part of the code.
     pub fn read_cpu_mem(&self, address: u16) -> Result<u8, MemoryError> {
         match address {
             // RAM reading, including mirroring
@@ -190,73 +518,96 @@ impl Memory {
             // NES PPU registers
             0x2000..0x4000 => self.read_ppu_byte(address - 0x2000),
             // Open bus, undefined behavior
-            0x4000..0x4016 => Ok(0),
-            0x4016 => {
+            0x4000..0x4015 => Ok(0),
+            // APU status, reading it through this function does not acknowledge the frame interrupt
+            0x4015 => Ok(self.apu.borrow().peek_status()),
+            0x4016 | 0x4017 => {
                 warn!("You have to use the read function if you want to access the controller");
                 Ok(0)
             }
-            0x4017 => {
-                // TODO: impelement controller 2
-                Ok(0)
-            }
             // Open bus, undefined behavior
             0x4018..0x4020 => Ok(0),
             // Cartridge memory
             0x4020..0x6000 => Ok(0),
-            0x6000.. => Ok(self.cartridge.read(address)?),
+            0x6000.. => Ok(self.cartridge.read(address)),
+        }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if let Some(file) = &mut self.battery_file {
+            if let Err(e) = file.flush(self.cartridge.banks.prg_ram()) {
+                log::error!("could not save battery RAM: {}", e);
+            }
         }
     }
 }
 
+// The version of the header format a ROM image uses
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
-pub enum ProgramBankMode {
-    Fullswitch,
-    Fixfirst,
-    Fixlast,
+pub enum RomFormat {
+    ArchaicInes, // iNES with bytes 7-15 filled with junk, like the name of the dumping tool
+    Ines,
+    Nes2,
 }
 
+// The CPU and PPU timing a ROM image is made for
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
-pub enum CharacterBankMode {
-    Fullswitch,
-    Halfswitch,
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ConsoleType {
+    Nes, // Also the Famicom and Dendy
+    VsSystem,
+    Playchoice10,
+    Extended(u8), // One of the extended console types of NES 2.0, see byte 13 of the header
 }
 
 #[derive(Debug, PartialEq)]
 pub struct RomHeader {
+    format: RomFormat,
     mirroring: Mirroring,
     peristent_memory: bool,
     ignore_mirroring_control: bool,
     trainer: bool,
-    program_rom_size: u8,
-    program_ram_size: u8,
-    charactor_memory_size: u8,
-    mapper_number: u8,
+    program_rom_size: usize,      // Size of the PRG ROM in bytes
+    program_ram_size: usize,      // Size of the volatile PRG RAM in bytes
+    program_nvram_size: usize,    // Size of the battery-backed PRG RAM in bytes
+    charactor_memory_size: usize, // Size of the CHR ROM in bytes
+    character_ram_size: usize,    // Size of the volatile CHR RAM in bytes
+    character_nvram_size: usize,  // Size of the battery-backed CHR RAM in bytes
+    mapper_number: u16,
+    submapper: u8,
+    timing: Timing,
+    console_type: ConsoleType,
+    misc_rom_count: u8, // Number of miscellaneous ROM areas stored after the CHR ROM
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Cartridge {
     header: RomHeader,
-    prg_data: Vec<u8>,
-    chr_data: Vec<u8>,
-    prg_bank: u8,
-    chr_bank_0: u8,
-    chr_bank_1: u8,
-    shift_register: u8,
-    prg_bank_mode: ProgramBankMode,
-    chr_bank_mode: CharacterBankMode,
-    pgr_ram: [u8; 8192], // 8 KiB of program ram
-    chr_ram: [u8; 8192],
-    init_code: Vec<u8>,
+    banks: Banks,
+    mapper: Box<dyn Mapper>,
+    checksum: [u8; 16], // MD5 of the PRG and CHR ROM
 }
 
 // A struct handling parsing of Ines files and mapping it to an address space.
 //
-// It implements the NROM and MMC1 mappers.
+// The mapping is done by one of the mappers in the `mappers` module.
 impl Cartridge {
-    // Parse the header of an Ines file
-    //
+    // Parse the header of an iNES or NES 2.0 file
     //
+    // See https://www.nesdev.org/wiki/INES and https://www.nesdev.org/wiki/NES_2.0
     fn parse_header(rom_bytes: &[u8]) -> Result<RomHeader, RomError> {
+        if rom_bytes.len() < 16 {
+            return Err(RomError::TooShort(rom_bytes.len()));
+        }
         // Check rom signature
         if rom_bytes[0..4] != *(b"NES\x1a") {
             log::debug!("{:?}", b"NES\x1a");
@@ -265,247 +616,241 @@ impl Cartridge {
                 "Header signature does not match specification".to_string(),
             ));
         }
+        let header = &rom_bytes[0..16];
+
+        let format = if header[7] & 0x0C == 0x08 {
+            RomFormat::Nes2
+        } else if header[7] & 0x0C == 0 && header[12..16] == [0; 4] {
+            RomFormat::Ines
+        } else {
+            RomFormat::ArchaicInes
+        };
+        let peristent_memory = (header[6] >> 1 & 1) != 0;
+
+        let mut mapper_number = (header[6] >> 4) as u16;
+        if format != RomFormat::ArchaicInes {
+            mapper_number |= (header[7] & 0xF0) as u16;
+        }
 
         // Parse rom header
-        Ok(RomHeader {
-            program_rom_size: rom_bytes[4],
-            charactor_memory_size: rom_bytes[5],
-            mirroring: if (rom_bytes[6] & 1) != 0 {
+        let mut rom_header = RomHeader {
+            format,
+            mirroring: if (header[6] & 1) != 0 {
                 Mirroring::Vertical
             } else {
                 Mirroring::Horizontal
             },
-            ignore_mirroring_control: (rom_bytes[6] >> 3 & 1) != 0,
-            peristent_memory: (rom_bytes[6] >> 1 & 1) != 0,
-            trainer: (rom_bytes[6] >> 2 & 1) != 0,
-            program_ram_size: rom_bytes[8],
-            mapper_number: (rom_bytes[6] >> 4) | (rom_bytes[7] & 0b11110000),
-        })
+            ignore_mirroring_control: (header[6] >> 3 & 1) != 0,
+            peristent_memory,
+            trainer: (header[6] >> 2 & 1) != 0,
+            program_rom_size: header[4] as usize * 0x4000,
+            program_ram_size: 0,
+            program_nvram_size: 0,
+            charactor_memory_size: header[5] as usize * 0x2000,
+            character_ram_size: 0,
+            character_nvram_size: 0,
+            mapper_number,
+            submapper: 0,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+            misc_rom_count: 0,
+        };
+
+        match format {
+            RomFormat::Nes2 => {
+                rom_header.mapper_number |= ((header[8] & 0x0F) as u16) << 8;
+                rom_header.submapper = header[8] >> 4;
+                rom_header.program_rom_size = rom_size(header[4], header[9] & 0x0F, 0x4000);
+                rom_header.charactor_memory_size = rom_size(header[5], header[9] >> 4, 0x2000);
+                rom_header.program_ram_size = ram_size(header[10] & 0x0F);
+                rom_header.program_nvram_size = ram_size(header[10] >> 4);
+                rom_header.character_ram_size = ram_size(header[11] & 0x0F);
+                rom_header.character_nvram_size = ram_size(header[11] >> 4);
+                rom_header.timing = match header[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                rom_header.console_type = match header[7] & 0b11 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Extended(header[13] & 0x0F),
+                };
+                rom_header.misc_rom_count = header[14] & 0b11;
+            }
+            _ => {
+                // iNES does not store the RAM sizes, most boards that have PRG RAM have 8 KiB and
+                // boards without CHR ROM have 8 KiB of CHR RAM
+                let program_ram_size = match (format, header[8]) {
+                    (RomFormat::Ines, banks @ 1..) => banks as usize * 0x2000,
+                    _ => 0x2000,
+                };
+                if peristent_memory {
+                    rom_header.program_nvram_size = program_ram_size;
+                } else {
+                    rom_header.program_ram_size = program_ram_size;
+                }
+                if rom_header.charactor_memory_size == 0 {
+                    rom_header.character_ram_size = 0x2000;
+                }
+                if format == RomFormat::Ines {
+                    if header[9] & 1 != 0 {
+                        rom_header.timing = Timing::Pal;
+                    }
+                    if header[7] & 1 != 0 {
+                        rom_header.console_type = ConsoleType::VsSystem;
+                    } else if header[7] & 2 != 0 {
+                        rom_header.console_type = ConsoleType::Playchoice10;
+                    }
+                }
+            }
+        }
+        Ok(rom_header)
     }
 
     fn new(rom_bytes: &[u8]) -> Result<Cartridge, RomError> {
         let header = Self::parse_header(rom_bytes)?;
 
-        // generate warning if mapper is not implemented
-        if header.mapper_number > 1 {
-            warn!("Mapper {} not supported", header.mapper_number);
-        }
-        // check if the total length of the given .nes file actually corresponds to the header
-        let mut total_length: u32 =
-            header.charactor_memory_size as u32 * 8192 + header.program_rom_size as u32 * 16384;
-        if header.trainer {
-            total_length += 512
-        }
-        if rom_bytes[16..].len() != total_length as usize {
-            return Err(RomError::IncorrectDataSize(
-                "Given amount of data does not match header".to_string(),
-            ));
+        log::info!(
+            "{:?} header, mapper {}.{}, {:?} timing, console {:?}",
+            header.format,
+            header.mapper_number,
+            header.submapper,
+            header.timing,
+            header.console_type
+        );
+        if header.timing == Timing::Pal || header.timing == Timing::Dendy {
+            warn!("Only NTSC timing is emulated");
         }
-        // create start and end index to find the program rom from within the .nes file
-        let prg_rom_start_index: usize = 16 + (header.trainer as usize) * 512_usize;
-        let prg_rom_end_index: usize =
-            16 + (header.trainer as usize) * 512 + (header.program_rom_size as usize) * 0x4000;
+        let (prg_rom_start_index, prg_rom_end_index, chr_rom_end_index) =
+            Self::rom_layout(&header, rom_bytes)?;
+        let checksum = md5(&rom_bytes[prg_rom_start_index..chr_rom_end_index]);
         let cartridge_prg_rom: Vec<u8> = rom_bytes[prg_rom_start_index..prg_rom_end_index].to_vec();
-        let mut cartridge_chr_rom: Vec<u8> = vec![];
-        if header.charactor_memory_size != 0 {
-            cartridge_chr_rom.append(&mut rom_bytes[prg_rom_end_index..].to_vec());
-        } else {
-            let chr_ram: [u8; 8192] = [0; 8192];
-            cartridge_chr_rom.append(&mut chr_ram.to_vec());
+        let cartridge_chr_rom: Vec<u8> = rom_bytes[prg_rom_end_index..chr_rom_end_index].to_vec();
+        let mut pgr_ram = vec![0; header.program_ram_size + header.program_nvram_size];
+        if header.trainer {
+            // the trainer is loaded into PRG RAM at $7000-$71FF, so there has to be 8 KiB of it
+            if pgr_ram.len() < 0x2000 {
+                pgr_ram.resize(0x2000, 0);
+            }
+            pgr_ram[0x1000..0x1200].copy_from_slice(&rom_bytes[16..prg_rom_start_index]);
         }
-        // copy the last 256 bytes of the program rom into a seperate vector to be able to always access these bytes
-        let cartridge_init_code: Vec<u8> = rom_bytes[(prg_rom_end_index - 256)..].to_vec();
+        let chr_ram_size = header.character_ram_size + header.character_nvram_size;
         log::debug!("prg ram: {}", header.peristent_memory);
+        let mut banks = Banks::new(cartridge_prg_rom, pgr_ram, cartridge_chr_rom, chr_ram_size);
+        let mapper = mappers::new_mapper(&header, &mut banks)?;
         Ok(Cartridge {
             header,
-            prg_data: cartridge_prg_rom,
-            chr_data: cartridge_chr_rom,
-            prg_bank: 0,
-            chr_bank_0: 0,
-            chr_bank_1: 0,
-            shift_register: 16,
-            prg_bank_mode: ProgramBankMode::Fixlast,
-            chr_bank_mode: CharacterBankMode::Fullswitch,
-            pgr_ram: [0; 8192],
-            chr_ram: [0; 8192],
-            init_code: cartridge_init_code,
+            banks,
+            mapper,
+            checksum,
         })
     }
 
-    // Write to memory using one of the mappers
-    fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
-        match self.header.mapper_number {
-            0 => {
-                // memory mapping for the NROM mapper
-                match address {
-                    0x6000..0x8000 => {
-                        let ram_address: u16 = (address - 0x6000) & 0x7ff;
-                        self.pgr_ram[ram_address as usize] = value; // PGR RAM
-                    }
-                    0x8000.. => {
-                        let len = self.prg_data.len();
-                        self.prg_data[(address as usize) % len] = value
-                    } // prg rom
-                    _ => {
-                        return Err(MemoryError::UnknownAddress(
-                            "Address out of range - write".to_string(),
-                        ))
-                    }
-                }
-            }
-            1 => {
-                // check if the given command could be a reset for the mmc1 mapper
-                if (value & 0b10000000) == 128 {
-                    match address {
-                        0x6000..0x8000 => self.pgr_ram[(address - 0x6000) as usize] = value, // PGR RAM
-                        0x8000.. => {
-                            self.header.mirroring = Mirroring::SingleScreenLower;
-                            self.prg_bank_mode = ProgramBankMode::Fixlast;
-                            self.chr_bank_mode = CharacterBankMode::Fullswitch;
-                        }
-                        _ => return Ok(()),
-                    }
-                    // check if the shift register is full or not
-                } else if (self.shift_register & 1) != 1 {
-                    self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
-                } else {
-                    // if the shift register is full shift in the fifth bit and write it to the appropriate address
-                    self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
-                    match address {
-                        0x6000..0x8000 => self.pgr_ram[(address - 0x6000) as usize] = value, // PGR RAM
-                        0x8000..0xa000 => {
-                            log::debug!("editing control register to {:08b}", self.shift_register);
-                            // set mirroring
-                            match self.shift_register & 3 {
-                                0 => self.header.mirroring = Mirroring::SingleScreenLower,
-                                1 => self.header.mirroring = Mirroring::SingleScreenUpper,
-                                2 => self.header.mirroring = Mirroring::Horizontal,
-                                3 => self.header.mirroring = Mirroring::Vertical,
-                                _ => {
-                                    return Err(MemoryError::ShiftAddressError(
-                                        "Match shift register & 3".to_string(),
-                                    ))
-                                }
-                            }
-                            // set program bank shifting mode
-                            match (self.shift_register >> 2) & 3 {
-                                0 | 1 => self.prg_bank_mode = ProgramBankMode::Fullswitch,
-                                2 => self.prg_bank_mode = ProgramBankMode::Fixfirst,
-                                3 => self.prg_bank_mode = ProgramBankMode::Fixlast,
-                                _ => {
-                                    return Err(MemoryError::ShiftAddressError(
-                                        "Match shift register >>2 & 3".to_string(),
-                                    ))
-                                }
-                            }
-                            // set character bank mode
-                            if (self.shift_register >> 4) & 1 == 0 {
-                                log::debug!("changed chr bank mode to fullswitch");
-                                self.chr_bank_mode = CharacterBankMode::Fullswitch
-                            } else {
-                                log::debug!("changed chr bank mode to halfswitch");
-                                self.chr_bank_mode = CharacterBankMode::Halfswitch
-                            }
-                        }
-                        0xa000..0xc000 => {
-                            log::debug!("editing chr0 register to {:08b}", self.shift_register);
-                            self.chr_bank_0 = self.shift_register;
-                        }
-                        0xc000..0xe000 => {
-                            log::debug!("editing chr1 register to {:08b}", self.shift_register);
-                            self.chr_bank_1 = self.shift_register;
-                        }
-                        0xe000.. => {
-                            log::debug!("editing prg register to {:08b}", self.shift_register);
-                            self.prg_bank = self.shift_register;
-                        }
-                        _ => {
-                            return Err(MemoryError::MapperAddressError(
-                                address,
-                                "MMC1 address error".to_string(),
-                            ))
-                        }
-                    }
-                    self.shift_register = 16;
-                }
-            }
-            a => Err(RomError::UnknownMapper(a, "Unknown error".to_string()))?,
+    // Returns where the PRG ROM starts and ends and where the CHR ROM ends in an iNES file
+    //
+    // Checks that the length of the file corresponds to the header: the trainer and PRG ROM
+    // follow the header, and the CHR ROM follows those.
+    fn rom_layout(header: &RomHeader, rom_bytes: &[u8]) -> Result<(usize, usize, usize), RomError> {
+        if header.program_rom_size == 0 {
+            return Err(RomError::NoProgramRom);
         }
-        Ok(())
+        let prg_rom_start_index: usize = 16 + (header.trainer as usize) * 512_usize;
+        let prg_rom_end_index = prg_rom_start_index.saturating_add(header.program_rom_size);
+        if rom_bytes.len() < prg_rom_end_index {
+            return Err(RomError::TruncatedProgramRom(
+                header.program_rom_size,
+                rom_bytes.len().saturating_sub(prg_rom_start_index),
+            ));
+        }
+        let chr_rom_end_index = prg_rom_end_index.saturating_add(header.charactor_memory_size);
+        if rom_bytes.len() < chr_rom_end_index {
+            return Err(RomError::TruncatedCharacterRom(
+                header.charactor_memory_size,
+                rom_bytes.len() - prg_rom_end_index,
+            ));
+        }
+        // only NES 2.0 files can have miscellaneous ROM areas after the character rom
+        if rom_bytes.len() > chr_rom_end_index && header.misc_rom_count == 0 {
+            return Err(RomError::TrailingData(rom_bytes.len() - chr_rom_end_index));
+        }
+        Ok((prg_rom_start_index, prg_rom_end_index, chr_rom_end_index))
     }
 
-    // Read from memory using one of the mappers
-    fn read(&self, address: u16) -> Result<u8, RomError> {
-        match self.header.mapper_number {
-            0 => {
-                match address {
-                    0x6000..0x8000 => Ok(self.pgr_ram[(address - 0x6000) as usize]), // PGR RAM
-                    0x8000..0xff00 => {
-                        let len = self.prg_data.len();
-                        Ok(self.prg_data[address as usize % len])
-                    } // prg rom
-                    0xff00.. => Ok(self.init_code[(address - 0xff00) as usize]),
-                    _ => Err(RomError::UnknownAddress("read error mapper 0".to_string())),
-                }
-            }
-            1 => {
-                match self.prg_bank_mode {
-                    ProgramBankMode::Fullswitch => {
-                        let banknr = (self.prg_bank & 0x0F) >> 1;
-                        match address {
-                            0x6000..0x8000 => Ok(self.pgr_ram[(address - 0x6000) as usize]), // PGR RAM
-                            0x8000.. => {
-                                let target: u32 =
-                                    address as u32 - 0x8000 + (banknr as u32 * 0x8000);
-                                Ok(self.prg_data[target as usize])
-                            } // switch in 32kb blocks
-                            _ => Err(RomError::UnknownAddress(
-                                "read error mapper 1 fullswitch".to_string(),
-                            )),
-                        }
-                    }
-                    ProgramBankMode::Fixfirst => {
-                        let banknr = self.prg_bank & 0x0F;
-                        match address {
-                            0x6000..0x8000 => Ok(self.pgr_ram[(address - 0x6000) as usize]), // PGR RAM
-                            0x8000..0xc000 => Ok(self.prg_data[(address - 0x8000) as usize]), // fix first bank to 0x8000
-                            0xc000.. => {
-                                let target: u32 =
-                                    address as u32 - 0xc000 + (banknr as u32) * 0x4000;
-                                Ok(self.prg_data[target as usize]) // make 0xc000 - 0x switchable
-                            }
-                            _ => Err(RomError::UnknownAddress(
-                                "read error mapper 1 fixfirst".to_string(),
-                            )),
-                        }
-                    }
-                    ProgramBankMode::Fixlast => {
-                        let banknr = self.prg_bank & 0x0F;
-                        match address {
-                            0x6000..0x8000 => Ok(self.pgr_ram[(address - 0x6000) as usize]), // PGR RAM
-                            0x8000..0xc000 => {
-                                let target: u32 = address as u32 - 0x8000 + (banknr as u32) * 16384;
-                                Ok(self.prg_data[target as usize]) // make 0x8000 - 0xc000 switchable
-                            }
-                            0xc000..0xff00 => {
-                                let target: u32 = address as u32 - 0xc000
-                                    + ((self.header.program_rom_size - 1) as u32) * 16384;
-                                Ok(self.prg_data[target as usize]) // Fix last bank to 0xc000
-                            }
-                            0xff00.. => Ok(self.init_code[(address - 0xff00) as usize]),
-                            _ => Err(RomError::UnknownAddress(
-                                "read error mapper 1 fixlast".to_string(),
-                            )),
-                        }
-                    }
-                }
-            }
-            a => Err(RomError::UnknownMapper(
-                a,
-                "read - unknown mapper".to_string(),
-            ))?,
+    // Returns the PRG ROM of an iNES file without creating a mapper, for tools like the
+    // disassembler that also work on ROMs with mappers that aren't emulated
+    pub fn prg_rom(rom_bytes: &[u8]) -> Result<&[u8], RomError> {
+        let header = Self::parse_header(rom_bytes)?;
+        let (start, end, _) = Self::rom_layout(&header, rom_bytes)?;
+        Ok(&rom_bytes[start..end])
+    }
+
+    // Recreates the mapper, which maps the banks it starts with
+    fn power_cycle(&mut self) {
+        self.mapper = mappers::new_mapper(&self.header, &mut self.banks)
+            .expect("the mapper was created when the ROM was loaded");
+    }
+
+    // Returns the nametable mirroring, which the mapper can change unless there is four-screen VRAM
+    fn mirroring(&self) -> Mirroring {
+        if self.header.ignore_mirroring_control {
+            // the cartridge provides memory for all four nametables
+            Mirroring::FourScreen
+        } else {
+            self.mapper.mirroring().unwrap_or(self.header.mirroring)
         }
     }
+
+    // Returns true for mappers that can change the mirroring at runtime
+    fn has_dynamic_mirroring(&self) -> bool {
+        self.mapper.mirroring().is_some() && !self.header.ignore_mirroring_control
+    }
+
+    // Write to memory using the mapper
+    fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+        self.mapper.write_cpu(&mut self.banks, address, value)
+    }
+
+    // Read from memory using the mapper
+    fn read(&self, address: u16) -> u8 {
+        self.mapper.read_cpu(&self.banks, address)
+    }
+
+    // Saves the RAM of the cartridge and the state of the mapper
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.banks.save_state(state);
+        self.mapper.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.banks.load_state(state)?;
+        self.mapper.load_state(state)
+    }
+}
+
+// Decodes a NES 2.0 ROM size in bytes, from the LSB in byte 4 or 5 and the MSB nibble in byte 9
+//
+// An MSB of 0xF means the LSB holds an exponent and a multiplier instead of a number of units.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .and_then(|size| size.checked_mul(multiplier))
+            .unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+// Decodes a NES 2.0 RAM size in bytes, which is stored as a shift count
+fn ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
 }
 
 #[cfg(test)]
@@ -514,17 +859,482 @@ use tudelft_nes_test::ROM_NROM_TEST;
 #[test]
 fn test_parse_header() {
     let expected_header = RomHeader {
+        format: RomFormat::Ines,
         mirroring: Mirroring::Horizontal,
         trainer: false,
         peristent_memory: false,
         ignore_mirroring_control: false,
-        program_ram_size: 0,
-        program_rom_size: 1,
-        charactor_memory_size: 1,
+        program_rom_size: 0x4000,
+        program_ram_size: 0x2000,
+        program_nvram_size: 0,
+        charactor_memory_size: 0x2000,
+        character_ram_size: 0,
+        character_nvram_size: 0,
         mapper_number: 0,
+        submapper: 0,
+        timing: Timing::Ntsc,
+        console_type: ConsoleType::Nes,
+        misc_rom_count: 0,
     };
     assert_eq!(
         Cartridge::parse_header(ROM_NROM_TEST).unwrap(),
         expected_header
     );
 }
+
+#[test]
+fn test_parse_nes2_header() {
+    // MMC3 submapper 1 with a battery, 128 KiB PRG ROM, a CHR ROM size written as an exponent,
+    // 8 KiB PRG NVRAM, 32 KiB CHR RAM and PAL timing
+    let rom = b"NES\x1a\x08\x4D\x42\x08\x10\xF0\x70\x09\x01\x00\x00\x00";
+    let header = Cartridge::parse_header(rom).unwrap();
+    assert_eq!(header.format, RomFormat::Nes2);
+    assert_eq!(header.mapper_number, 4);
+    assert_eq!(header.submapper, 1);
+    assert_eq!(header.program_rom_size, 0x20000);
+    assert_eq!(header.charactor_memory_size, (1 << 19) * 3);
+    assert_eq!(header.program_ram_size, 0);
+    assert_eq!(header.program_nvram_size, 0x2000);
+    assert_eq!(header.character_ram_size, 0x8000);
+    assert_eq!(header.timing, Timing::Pal);
+    assert_eq!(header.console_type, ConsoleType::Nes);
+
+    // Junk in bytes 7-15 means the upper nibble of the mapper number can't be trusted
+    let rom = b"NES\x1a\x01\x01\x10\x44\x69\x73\x6B\x44\x75\x64\x65\x21";
+    let header = Cartridge::parse_header(rom).unwrap();
+    assert_eq!(header.format, RomFormat::ArchaicInes);
+    assert_eq!(header.mapper_number, 1);
+}
+
+#[test]
+fn test_malformed_roms_are_rejected() {
+    assert_eq!(Memory::new(b"NES\x1a").unwrap_err(), RomError::TooShort(4));
+
+    let mut rom = ROM_NROM_TEST.to_vec();
+    rom[4] = 0;
+    assert_eq!(Memory::new(&rom).unwrap_err(), RomError::NoProgramRom);
+
+    assert_eq!(
+        Memory::new(&ROM_NROM_TEST[..0x1000]).unwrap_err(),
+        RomError::TruncatedProgramRom(0x4000, 0x1000 - 16)
+    );
+    assert_eq!(
+        Memory::new(&ROM_NROM_TEST[..16 + 0x5000]).unwrap_err(),
+        RomError::TruncatedCharacterRom(0x2000, 0x1000)
+    );
+
+    let mut rom = ROM_NROM_TEST.to_vec();
+    rom.extend([0; 3]);
+    assert_eq!(Memory::new(&rom).unwrap_err(), RomError::TrailingData(3));
+
+    // A CHR ROM size written as a huge exponent must not overflow
+    let mut rom = ROM_NROM_TEST.to_vec();
+    rom[5] = 0xFF;
+    rom[7] = 0x08;
+    rom[9] = 0xF0;
+    assert!(matches!(
+        Memory::new(&rom).unwrap_err(),
+        RomError::TruncatedCharacterRom(..)
+    ));
+
+    let mut rom = ROM_NROM_TEST.to_vec();
+    rom[6] = 0xF0;
+    assert!(matches!(
+        Memory::new(&rom).unwrap_err(),
+        RomError::UnknownMapper(15, _)
+    ));
+
+    // No prefix of a valid file loads, nor panics
+    for length in 0..ROM_NROM_TEST.len() {
+        assert!(Memory::new(&ROM_NROM_TEST[..length]).is_err());
+    }
+}
+
+#[test]
+fn test_trainer_is_loaded_at_7000() {
+    let mut rom = b"NES\x1a\x01\x01\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    rom.extend([0x42; 512]);
+    rom.extend([0xEA; 0x4000]);
+    rom.extend([0; 0x2000]);
+    let memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x6FFF).unwrap(), 0);
+    assert_eq!(memory.read_cpu_mem(0x7000).unwrap(), 0x42);
+    assert_eq!(memory.read_cpu_mem(0x71FF).unwrap(), 0x42);
+    assert_eq!(memory.read_cpu_mem(0x7200).unwrap(), 0);
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 0xEA);
+}
+
+#[test]
+fn test_ram_sizes_from_nes2_header() {
+    // UxROM with 2 KiB PRG RAM and 16 KiB CHR RAM
+    let mut rom = b"NES\x1a\x02\x00\x20\x08\x00\x00\x05\x08\x00\x00\x00\x00".to_vec();
+    rom.extend([0; 0x8000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.cartridge.banks.prg_ram().len(), 0x800);
+    assert_eq!(memory.cartridge.header.character_ram_size, 0x4000);
+
+    // The PRG RAM is mirrored through $6000-$7FFF
+    memory.cartridge.write(0x6000, 0x42).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x6800).unwrap(), 0x42);
+    memory.write_ppu_byte(0x1FFF, 0x43).unwrap();
+    assert_eq!(memory.read_ppu_byte(0x1FFF).unwrap(), 0x43);
+}
+
+#[test]
+fn test_ppu_mirroring() {
+    let mut rom = b"NES\x1a\x01\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    rom.extend([0; 0x6000]);
+    assert_eq!(
+        Memory::new(&rom).unwrap().ppu_mirroring(),
+        Mirroring::Vertical
+    );
+
+    // Four-screen VRAM on the cartridge overrides the mirroring bit
+    rom[6] = 0b0000_1001;
+    assert_eq!(
+        Memory::new(&rom).unwrap().ppu_mirroring(),
+        Mirroring::FourScreen
+    );
+
+    // MMC1 can switch the mirroring, so it is emulated on top of a four-screen PPU
+    rom[6] = 0b0001_0001;
+    let memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.mirroring(), Mirroring::Vertical);
+    assert_eq!(memory.ppu_mirroring(), Mirroring::FourScreen);
+}
+
+#[test]
+fn test_prg_rom_mirroring_and_vectors() {
+    // NROM-128 mirrors its 16 KiB, the vectors come from the end of the PRG ROM and not the CHR ROM
+    let mut rom = b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    rom.extend((0..0x4000).map(|i| (i >> 8) as u8));
+    rom.extend([0xFF; 0x2000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x8100).unwrap(), 0x01);
+    assert_eq!(memory.read_cpu_mem(0xC100).unwrap(), 0x01);
+    assert_eq!(memory.read_cpu_mem(0xFFFC).unwrap(), 0x3F);
+    // Expansion space is open bus
+    assert!(memory.cartridge.write(0x5000, 0).is_ok());
+
+    // MMC1 starts with the last bank fixed at $C000
+    let mut rom = b"NES\x1a\x04\x01\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..4 {
+        rom.extend([bank; 0x4000]);
+    }
+    rom.extend([0xFF; 0x2000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 0);
+    assert_eq!(memory.read_cpu_mem(0xFFFC).unwrap(), 3);
+    assert!(memory.cartridge.write(0x5000, 0).is_ok());
+}
+
+#[test]
+fn test_uxrom_banking() {
+    // 4 banks of 16 KiB filled with their bank number, and CHR RAM
+    let mut rom = b"NES\x1a\x04\x00\x20\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..4 {
+        rom.extend([bank; 0x4000]);
+    }
+    let mut cartridge = Cartridge::new(&rom).unwrap();
+    assert_eq!(cartridge.read(0x8000), 0);
+    assert_eq!(cartridge.read(0xFFFF), 3);
+
+    cartridge.write(0x8000, 2).unwrap();
+    assert_eq!(cartridge.read(0xBFFF), 2);
+    assert_eq!(cartridge.read(0xC000), 3);
+
+    // Bank numbers wrap around the size of the PRG ROM
+    cartridge.write(0xFFFF, 5).unwrap();
+    assert_eq!(cartridge.read(0x8000), 1);
+
+    // Expansion space is open bus
+    assert!(cartridge.write(0x5000, 2).is_ok());
+    assert_eq!(cartridge.read(0x8000), 1);
+}
+
+#[test]
+fn test_cnrom_chr_banking() {
+    // 2 banks of 8 KiB CHR ROM filled with their bank number
+    let mut rom = b"NES\x1a\x01\x02\x30\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    rom.extend([0xEA; 0x4000]);
+    rom.extend([0; 0x2000]);
+    rom.extend([1; 0x2000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.read_ppu_byte(0x1FFF).unwrap(), 0);
+
+    memory.cartridge.write(0x8000, 1).unwrap();
+    assert_eq!(memory.read_ppu_byte(0x0000).unwrap(), 1);
+    assert_eq!(memory.read_ppu_byte(0x1FFF).unwrap(), 1);
+
+    // Expansion space is open bus
+    assert!(memory.cartridge.write(0x5000, 0).is_ok());
+}
+
+#[test]
+fn test_axrom_banking_and_mirroring() {
+    // 4 banks of 32 KiB filled with their bank number, and CHR RAM
+    let mut rom = b"NES\x1a\x08\x00\x71\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..4 {
+        rom.extend([bank; 0x8000]);
+    }
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.mirroring(), Mirroring::SingleScreenLower);
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 0);
+
+    memory.cartridge.write(0x8000, 0b10010).unwrap();
+    assert_eq!(memory.mirroring(), Mirroring::SingleScreenUpper);
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 2);
+    assert_eq!(memory.read_cpu_mem(0xFFFF).unwrap(), 2);
+
+    // There is no PRG RAM, writes below $8000 are ignored
+    assert!(memory.cartridge.write(0x6000, 0x42).is_ok());
+    assert!(memory.cartridge.write(0x4020, 0x42).is_ok());
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 0);
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 2);
+}
+
+// Writes an MMC1 register through the serial port, a CPU cycle apart like a program would
+#[cfg(test)]
+fn write_mmc1(memory: &mut Memory, address: u16, value: u8) {
+    for bit in 0..5 {
+        memory.cartridge.write(address, value >> bit).unwrap();
+        memory.tick_mapper();
+        memory.tick_mapper();
+    }
+}
+
+#[test]
+fn test_mmc1_surom_and_chr_ram_banking() {
+    // 32 banks of 16 KiB filled with their bank number, and CHR RAM
+    let mut rom = b"NES\x1a\x20\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..32 {
+        rom.extend([bank; 0x4000]);
+    }
+    let mut memory = Memory::new(&rom).unwrap();
+    assert_eq!(memory.read_cpu_mem(0xC000).unwrap(), 15);
+
+    // Bit 4 of the CHR register selects the second 256 KiB, including the fixed bank
+    write_mmc1(&mut memory, 0xA000, 0x10);
+    write_mmc1(&mut memory, 0xE000, 3);
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 19);
+    assert_eq!(memory.read_cpu_mem(0xC000).unwrap(), 31);
+
+    // 4 KiB CHR mode banks the CHR RAM
+    write_mmc1(&mut memory, 0x8000, 0b11100);
+    write_mmc1(&mut memory, 0xA000, 1);
+    memory.write_ppu_byte(0x0000, 0x42).unwrap();
+    assert_eq!(memory.read_ppu_byte(0x0000).unwrap(), 0x42);
+    write_mmc1(&mut memory, 0xA000, 0);
+    assert_eq!(memory.read_ppu_byte(0x0000).unwrap(), 0);
+    write_mmc1(&mut memory, 0xC000, 1);
+    assert_eq!(memory.read_ppu_byte(0x1000).unwrap(), 0x42);
+}
+
+#[test]
+fn test_mmc1_prg_ram_banks_and_enable() {
+    // NES 2.0 header with 32 KiB PRG RAM, which makes it an SXROM board
+    let mut rom = b"NES\x1a\x02\x00\x10\x08\x00\x00\x09\x07\x00\x00\x00\x00".to_vec();
+    rom.extend([0xEA; 0x8000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    memory.cartridge.write(0x6000, 1).unwrap();
+    write_mmc1(&mut memory, 0xA000, 0b01000);
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 0);
+    memory.cartridge.write(0x6000, 2).unwrap();
+    write_mmc1(&mut memory, 0xA000, 0);
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 1);
+
+    // Bit 4 of the PRG register disables the PRG RAM
+    write_mmc1(&mut memory, 0xE000, 0x10);
+    memory.cartridge.write(0x6000, 3).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 0);
+    write_mmc1(&mut memory, 0xE000, 0);
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 1);
+}
+
+#[test]
+fn test_mmc1_ignores_consecutive_writes() {
+    let mut rom = b"NES\x1a\x08\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..8 {
+        rom.extend([bank; 0x4000]);
+    }
+    let mut memory = Memory::new(&rom).unwrap();
+
+    // Only the first of two writes on consecutive cycles reaches the shift register
+    for _ in 0..5 {
+        memory.cartridge.write(0xE000, 1).unwrap();
+        memory.tick_mapper();
+        memory.cartridge.write(0xE000, 0).unwrap();
+        memory.tick_mapper();
+        memory.tick_mapper();
+    }
+    assert_eq!(memory.read_cpu_mem(0x8000).unwrap(), 7); // Bank 15 wraps around to 7
+}
+
+// Builds an MMC3 image with 8 KiB PRG and 1 KiB CHR banks filled with their bank number
+#[cfg(test)]
+fn mmc3_test_rom() -> Vec<u8> {
+    let mut rom = b"NES\x1a\x04\x02\x40\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for bank in 0..8 {
+        rom.extend([bank; 0x2000]);
+    }
+    for bank in 0..16 {
+        rom.extend([bank; 0x400]);
+    }
+    rom
+}
+
+#[test]
+fn test_mmc3_banking() {
+    let mut memory = Memory::new(&mmc3_test_rom()).unwrap();
+    memory.cartridge.write(0x8000, 6).unwrap();
+    memory.cartridge.write(0x8001, 2).unwrap();
+    memory.cartridge.write(0x8000, 7).unwrap();
+    memory.cartridge.write(0x8001, 3).unwrap();
+    memory.cartridge.write(0x8000, 2).unwrap();
+    memory.cartridge.write(0x8001, 9).unwrap();
+    let prg_banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|a| memory.read_cpu_mem(a).unwrap());
+    assert_eq!(prg_banks, [2, 3, 6, 7]);
+    assert_eq!(memory.read_ppu_byte(0x1000).unwrap(), 9);
+
+    // Inverting both modes swaps the banks at $8000 and $C000 and the pattern table halves
+    memory.cartridge.write(0x8000, 0b1100_0000).unwrap();
+    let prg_banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|a| memory.read_cpu_mem(a).unwrap());
+    assert_eq!(prg_banks, [6, 3, 2, 7]);
+    assert_eq!(memory.read_ppu_byte(0x0000).unwrap(), 9);
+
+    memory.cartridge.write(0xA000, 1).unwrap();
+    assert_eq!(memory.mirroring(), Mirroring::Horizontal);
+
+    // Expansion space is open bus
+    assert!(memory.cartridge.write(0x5000, 0).is_ok());
+    assert_eq!(memory.read_cpu_mem(0xA000).unwrap(), 3);
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+    let mut memory = Memory::new(&mmc3_test_rom()).unwrap();
+    memory.ppu_ctrl = 0b0000_1000; // Sprites at $1000
+    memory.ppu_mask = 0b0001_1000;
+    memory.cartridge.write(0xC000, 5).unwrap();
+    memory.cartridge.write(0xC001, 0).unwrap();
+    memory.cartridge.write(0xE001, 0).unwrap();
+
+    // The counter is reloaded on scanline 0 and reaches 0 on scanline 5 at dot 260
+    for _ in 0..(5 * 341 + 260) / 3 {
+        memory.tick_ppu_timing();
+    }
+    assert!(!memory.mapper_irq());
+    memory.tick_ppu_timing();
+    assert!(memory.mapper_irq());
+
+    memory.cartridge.write(0xE000, 0).unwrap();
+    assert!(!memory.mapper_irq());
+}
+
+#[test]
+fn test_cartridge_save_state() {
+    let mut memory = Memory::new(&mmc3_test_rom()).unwrap();
+    memory.cartridge.write(0x8000, 6).unwrap();
+    memory.cartridge.write(0x8001, 2).unwrap();
+    memory.cartridge.write(0xA000, 1).unwrap();
+    memory.cartridge.write(0x6000, 0x42).unwrap();
+    let mut state = StateWriter::new();
+    memory.cartridge.save_state(&mut state);
+    let state = state.into_bytes();
+
+    let mut loaded = Memory::new(&mmc3_test_rom()).unwrap();
+    let mut reader = StateReader::new(&state);
+    loaded.cartridge.load_state(&mut reader).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(loaded.read_cpu_mem(0x8000).unwrap(), 2);
+    assert_eq!(loaded.read_cpu_mem(0x6000).unwrap(), 0x42);
+    assert_eq!(loaded.mirroring(), Mirroring::Horizontal);
+
+    // A truncated state is an error instead of a partially loaded one
+    let mut reader = StateReader::new(&state[..state.len() - 1]);
+    assert_eq!(
+        loaded.cartridge.load_state(&mut reader),
+        Err(SaveStateError::UnexpectedEnd)
+    );
+}
+
+#[test]
+fn test_battery_file() {
+    let path = std::env::temp_dir().join(format!("nes-emulator-{}.sav", std::process::id()));
+    std::fs::write(&path, [0x42; 8192]).unwrap();
+
+    // NROM with a battery
+    let mut rom = b"NES\x1a\x01\x01\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    rom.extend([0; 0x6000]);
+    let mut memory = Memory::new(&rom).unwrap();
+    memory.set_battery_file(path.clone()).unwrap();
+    assert_eq!(memory.read_cpu_mem(0x6000).unwrap(), 0x42);
+
+    memory.cartridge.write(0x6000, 0x43).unwrap();
+    drop(memory);
+    let saved = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved[..2], [0x43, 0x42]);
+}
+
+#[test]
+fn test_two_controllers() {
+    use tudelft_nes_ppu::Buttons;
+    use tudelft_nes_test::{TestableCpu, ROM_NROM_TEST};
+
+    let cpu = Cpu::get_cpu(ROM_NROM_TEST).unwrap();
+    let mut ppu = Ppu::new(Mirroring::Horizontal);
+    let mut memory = Memory::new(ROM_NROM_TEST).unwrap();
+    let player_1 = Buttons {
+        a: true,
+        ..Buttons::default()
+    };
+    let player_2 = Buttons {
+        b: true,
+        right: true,
+        ..Buttons::default()
+    };
+    memory.set_input_device(Port::One, Box::new(Controller::new(Box::new(player_1))));
+    memory.set_input_device(Port::Two, Box::new(Controller::new(Box::new(player_2))));
+
+    // A single write to $4016 strobes both controllers, which are shifted out independently
+    memory.write(0x4016, 1, &mut ppu).unwrap();
+    memory.write(0x4016, 0, &mut ppu).unwrap();
+    let mut read = |address| memory.read(address, &cpu, &mut ppu).unwrap();
+    assert_eq!(read(0x4016), 1);
+    assert_eq!(read(0x4017), 0);
+    assert_eq!(read(0x4017), 1);
+    let port_1: Vec<u8> = (1..8).map(|_| read(0x4016)).collect();
+    let port_2: Vec<u8> = (2..8).map(|_| read(0x4017)).collect();
+    assert_eq!(port_1, [0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(port_2, [0, 0, 0, 0, 0, 1]);
+}
+
+#[test]
+fn test_scripted_input_follows_frames() {
+    use input::{InputScript, ScriptedInput};
+    use std::sync::Arc;
+    use tudelft_nes_test::{TestableCpu, ROM_NROM_TEST};
+
+    let cpu = Cpu::get_cpu(ROM_NROM_TEST).unwrap();
+    let mut ppu = Ppu::new(Mirroring::Horizontal);
+    let mut memory = Memory::new(ROM_NROM_TEST).unwrap();
+    let script = Arc::new(InputScript::parse("1 a").unwrap());
+    let source = ScriptedInput::new(script, Port::One);
+    memory.set_input_device(Port::One, Box::new(Controller::new(Box::new(source))));
+
+    let mut read_a = |memory: &mut Memory| {
+        memory.write(0x4016, 1, &mut ppu).unwrap();
+        memory.write(0x4016, 0, &mut ppu).unwrap();
+        memory.read(0x4016, &cpu, &mut ppu).unwrap()
+    };
+    assert_eq!(read_a(&mut memory), 0);
+    for _ in 0..DOTS_PER_FRAME.div_ceil(3) {
+        memory.tick_ppu_timing();
+    }
+    assert_eq!(read_a(&mut memory), 1);
+    for _ in 0..DOTS_PER_FRAME.div_ceil(3) {
+        memory.tick_ppu_timing();
+    }
+    assert_eq!(read_a(&mut memory), 0);
+}
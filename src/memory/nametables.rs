@@ -0,0 +1,246 @@
+use crate::error::SaveStateError;
+use crate::savestate::{StateReader, StateWriter};
+use tudelft_nes_ppu::{Mirroring, Ppu, PpuRegister};
+
+// The mirrorings in the order they are numbered in save states
+const MIRRORINGS: [Mirroring; 5] = [
+    Mirroring::Horizontal,
+    Mirroring::Vertical,
+    Mirroring::SingleScreenLower,
+    Mirroring::SingleScreenUpper,
+    Mirroring::FourScreen,
+];
+
+// Returns the page of console VRAM that one of the four nametables is stored in
+fn page(mirroring: Mirroring, nametable: usize) -> usize {
+    match mirroring {
+        Mirroring::Horizontal => nametable >> 1,
+        Mirroring::Vertical => nametable & 1,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => nametable,
+    }
+}
+
+// Emulates nametable mirroring that changes at runtime, on a PPU whose mirroring is fixed
+//
+// The PPU is created with four-screen mirroring, which gives all four nametables their own memory.
+// Every write to a nametable is repeated through the PPU registers for the nametables mirroring
+// it, and when the mirroring changes all nametables are rewritten from the two pages of console
+// VRAM that are kept here. The PPU address register, the write latch and the selected nametable
+// are shadowed so they can be restored after writing through the registers.
+#[derive(Debug)]
+pub struct NametableMirror {
+    vram: [u8; 2048],     // The two pages of console VRAM
+    mirroring: Mirroring, // The mirroring that is being emulated
+    address: u16,         // Shadow of the PPU address register
+    latch: bool,          // Shadow of the PPU write latch, true when the next write is the first
+    ctrl: u8,             // Last value written to PPUCTRL
+    nametable: u8,        // Nametable selected through PPUCTRL or PPUADDR
+}
+
+impl NametableMirror {
+    pub fn new(mirroring: Mirroring) -> Self {
+        NametableMirror {
+            vram: [0; 2048],
+            mirroring,
+            address: 0,
+            latch: true,
+            ctrl: 0,
+            nametable: 0,
+        }
+    }
+
+    // Has to be called after every write to a PPU register
+    pub fn register_written(&mut self, register: PpuRegister, value: u8, ppu: &mut Ppu) {
+        match register {
+            PpuRegister::Controller => {
+                self.ctrl = value;
+                self.nametable = value & 0b11;
+            }
+            PpuRegister::Scroll => self.latch = !self.latch,
+            PpuRegister::Address => {
+                if self.latch {
+                    self.address = (self.address & 0x00FF) | ((value as u16) << 8) & 0x3FFF;
+                    self.nametable = (value >> 2) & 0b11;
+                } else {
+                    self.address = (self.address & 0xFF00) | value as u16;
+                }
+                self.latch = !self.latch;
+            }
+            PpuRegister::Data => {
+                // the PPU has already incremented its address, which is restored after mirroring
+                let address = self.address;
+                self.increment_address();
+                if let 0x2000..0x3F00 = address {
+                    self.nametable_written(address & 0x0FFF, value, ppu);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Has to be called after every read from a PPU register
+    pub fn register_read(&mut self, register: PpuRegister) {
+        match register {
+            PpuRegister::Status => self.latch = true,
+            PpuRegister::Data => self.increment_address(),
+            _ => {}
+        }
+    }
+
+    // Switches to another mirroring, rebuilding the nametables of the PPU when it changed
+    pub fn set_mirroring(&mut self, mirroring: Mirroring, ppu: &mut Ppu) {
+        if mirroring == self.mirroring {
+            return;
+        }
+        self.mirroring = mirroring;
+        self.rewrite(ppu);
+    }
+
+    // Writes all four nametables of the PPU from the console VRAM kept here
+    pub fn rewrite(&mut self, ppu: &mut Ppu) {
+        let mirroring = self.mirroring;
+        self.write_through_registers(ppu, |vram, writes| {
+            for offset in 0..0x1000 {
+                let page = page(mirroring, offset >> 10);
+                writes.push((
+                    0x2000 + offset as u16,
+                    vram[page * 0x400 + (offset & 0x3FF)],
+                ));
+            }
+        });
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        let mirroring = MIRRORINGS.iter().position(|&m| m == self.mirroring);
+        state.write_u8(mirroring.unwrap_or(0) as u8);
+        state.write_u16(self.address);
+        state.write_bool(self.latch);
+        state.write_u8(self.ctrl);
+        state.write_u8(self.nametable);
+    }
+
+    // Restores the state written by `save_state`, `rewrite` brings the PPU up to date with it
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_bytes_into(&mut self.vram)?;
+        let mirroring = state.read_u8()?;
+        self.mirroring = *MIRRORINGS
+            .get(mirroring as usize)
+            .ok_or_else(|| SaveStateError::Mismatch(format!("unknown mirroring {}", mirroring)))?;
+        self.address = state.read_u16()? & 0x3FFF;
+        self.latch = state.read_bool()?;
+        self.ctrl = state.read_u8()?;
+        self.nametable = state.read_u8()? & 0b11;
+        Ok(())
+    }
+
+    fn increment_address(&mut self) {
+        let increment = if self.ctrl & 0b100 != 0 { 32 } else { 1 };
+        self.address = (self.address + increment) & 0x3FFF;
+    }
+
+    // Stores a nametable write and repeats it for the nametables sharing the same page
+    fn nametable_written(&mut self, offset: u16, value: u8, ppu: &mut Ppu) {
+        let nametable = offset as usize >> 10;
+        let written_page = page(self.mirroring, nametable);
+        self.vram[written_page * 0x400 + (offset & 0x3FF) as usize] = value;
+
+        let mirroring = self.mirroring;
+        self.write_through_registers(ppu, |_, writes| {
+            for other in (0..4).filter(|&n| n != nametable && page(mirroring, n) == written_page) {
+                writes.push((0x2000 + (other << 10) as u16 + (offset & 0x3FF), value));
+            }
+        });
+    }
+
+    // Writes to PPU memory through the address and data registers, and restores their state
+    fn write_through_registers(
+        &mut self,
+        ppu: &mut Ppu,
+        collect: impl FnOnce(&[u8; 2048], &mut Vec<(u16, u8)>),
+    ) {
+        let mut writes = Vec::new();
+        collect(&self.vram, &mut writes);
+        if writes.is_empty() {
+            return;
+        }
+
+        // finish a half written address first, the PPU applies the first write immediately
+        if !self.latch {
+            ppu.write_ppu_register(PpuRegister::Address, self.address as u8);
+        }
+        // the data register increments by 1 or 32, writing the addresses in order needs 1
+        ppu.write_ppu_register(PpuRegister::Controller, self.ctrl & !0b100);
+        for (address, value) in writes {
+            ppu.write_ppu_register(PpuRegister::Address, (address >> 8) as u8);
+            ppu.write_ppu_register(PpuRegister::Address, address as u8);
+            ppu.write_ppu_register(PpuRegister::Data, value);
+        }
+
+        ppu.write_ppu_register(PpuRegister::Address, (self.address >> 8) as u8);
+        ppu.write_ppu_register(PpuRegister::Address, self.address as u8);
+        ppu.write_ppu_register(
+            PpuRegister::Controller,
+            (self.ctrl & !0b11) | self.nametable,
+        );
+        if !self.latch {
+            ppu.write_ppu_register(PpuRegister::Address, (self.address >> 8) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use tudelft_nes_test::{TestableCpu, ROM_NROM_TEST};
+
+    fn write(mirror: &mut NametableMirror, ppu: &mut Ppu, register: PpuRegister, value: u8) {
+        ppu.write_ppu_register(register, value);
+        mirror.register_written(register, value, ppu);
+    }
+
+    fn read_vram(ppu: &mut Ppu, address: u16) -> u8 {
+        let cpu = Cpu::get_cpu(ROM_NROM_TEST).unwrap();
+        ppu.write_ppu_register(PpuRegister::Address, (address >> 8) as u8);
+        ppu.write_ppu_register(PpuRegister::Address, address as u8);
+        ppu.read_ppu_register(PpuRegister::Data, &cpu); // Reads are buffered
+        ppu.read_ppu_register(PpuRegister::Data, &cpu)
+    }
+
+    #[test]
+    fn test_writes_are_mirrored() {
+        let mut ppu = Ppu::new(Mirroring::FourScreen);
+        let mut mirror = NametableMirror::new(Mirroring::Horizontal);
+        write(&mut mirror, &mut ppu, PpuRegister::Address, 0x20);
+        write(&mut mirror, &mut ppu, PpuRegister::Address, 0x10);
+        write(&mut mirror, &mut ppu, PpuRegister::Data, 0x42);
+        write(&mut mirror, &mut ppu, PpuRegister::Data, 0x43);
+        assert_eq!(mirror.address, 0x2012);
+
+        assert_eq!(read_vram(&mut ppu, 0x2010), 0x42);
+        assert_eq!(read_vram(&mut ppu, 0x2411), 0x43);
+        assert_eq!(read_vram(&mut ppu, 0x2810), 0x00);
+    }
+
+    #[test]
+    fn test_mirroring_change_rebuilds_nametables() {
+        let mut ppu = Ppu::new(Mirroring::FourScreen);
+        let mut mirror = NametableMirror::new(Mirroring::SingleScreenLower);
+        write(&mut mirror, &mut ppu, PpuRegister::Address, 0x2C);
+        write(&mut mirror, &mut ppu, PpuRegister::Address, 0x00);
+        write(&mut mirror, &mut ppu, PpuRegister::Data, 0x42);
+        assert_eq!(read_vram(&mut ppu, 0x2000), 0x42);
+
+        mirror.set_mirroring(Mirroring::Vertical, &mut ppu);
+        assert_eq!(read_vram(&mut ppu, 0x2000), 0x42);
+        assert_eq!(read_vram(&mut ppu, 0x2800), 0x42);
+        assert_eq!(read_vram(&mut ppu, 0x2400), 0x00);
+        assert_eq!(read_vram(&mut ppu, 0x2C00), 0x00);
+
+        mirror.set_mirroring(Mirroring::SingleScreenUpper, &mut ppu);
+        assert_eq!(read_vram(&mut ppu, 0x2000), 0x00);
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt::Debug;
+
+// Whether a byte was read from or written to the CPU bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// The part of the console that made an access
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessSource {
+    Cpu,
+    OamDma, // The sprite copy started by a write to $4014, reading a page and writing $2004
+    DmcDma, // A sample fetch of the DMC
+}
+
+// A read or write on the CPU bus, with the value that was read or written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub source: AccessSource,
+    pub address: u16,
+    pub value: u8,
+}
+
+// Told about every access to memory, including the DMA copies and the PPU registers
+//
+// Accesses through `Memory::read_cpu_mem` are not reported, as they don't happen on the bus.
+pub trait MemoryObserver: Send + Debug {
+    fn access(&mut self, access: Access);
+}
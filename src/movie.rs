@@ -0,0 +1,362 @@
+use crate::checksum::{base64_decode, base64_encode};
+use crate::error::MovieError;
+use crate::memory::input::{format_fm2_pad, parse_fm2_frame, InputScript, InputSource, Port};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tudelft_nes_ppu::{Buttons, Ppu};
+
+// The commands of a frame, in the first field of an FM2 input log
+pub const COMMAND_RESET: u8 = 1;
+pub const COMMAND_POWER: u8 = 2;
+
+// The input of one frame of a movie
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,       // Reset or power cycle at the start of the frame
+    pub pads: [Buttons; 2], // Buttons held on the pads in both ports
+}
+
+// A recording of the input for every frame since power-on, in the FM2 format of FCEUX
+//
+// Only movies with standard pads are supported. Playback is bit-exact for movies recorded by this
+// emulator, the input is read once per frame so timing differences with FCEUX can make its
+// movies desync.
+// https://fceux.com/web/FM2.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub rom_checksum: [u8; 16], // MD5 of the PRG and CHR ROM
+    pub ports: [bool; 2],       // Whether a pad is plugged into each port
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16]) -> Self {
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            ports: [true, true],
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new("", [0; 16]);
+        let mut checksum = None;
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |details: String| MovieError::InvalidLine(index + 1, details);
+            if line.starts_with('|') {
+                let (commands, pads) = parse_fm2_frame(line).map_err(invalid)?;
+                movie.frames.push(MovieFrame { commands, pads });
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "" => {}
+                "version" if value != "3" => {
+                    return Err(invalid(format!("unsupported version {}", value)))
+                }
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let digest = value
+                        .strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .and_then(|digest| <[u8; 16]>::try_from(digest).ok())
+                        .ok_or_else(|| invalid(format!("invalid checksum {}", value)))?;
+                    checksum = Some(digest);
+                }
+                "port0" | "port1" => {
+                    let port = usize::from(key == "port1");
+                    movie.ports[port] = match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(MovieError::Unsupported(format!("{} {}", key, value))),
+                    };
+                }
+                "fourscore" | "port2" | "FDS" if value != "0" => {
+                    return Err(MovieError::Unsupported(format!("{} {}", key, value)))
+                }
+                "savestate" => {
+                    return Err(MovieError::Unsupported(
+                        "starting from a save state".to_string(),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        movie.rom_checksum = checksum.ok_or(MovieError::MissingChecksum)?;
+        Ok(movie)
+    }
+
+    // Writes the header of an FM2 file, which is followed by a line for every frame
+    fn write_header(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "version 3")?;
+        writeln!(output, "rerecordCount 0")?;
+        writeln!(output, "palFlag 0")?;
+        writeln!(output, "romFilename {}", self.rom_filename)?;
+        writeln!(
+            output,
+            "romChecksum base64:{}",
+            base64_encode(&self.rom_checksum)
+        )?;
+        writeln!(output, "fourscore 0")?;
+        writeln!(output, "port0 {}", u8::from(self.ports[0]))?;
+        writeln!(output, "port1 {}", u8::from(self.ports[1]))?;
+        writeln!(output, "port2 0")
+    }
+
+    fn format_frame(&self, frame: &MovieFrame) -> String {
+        let mut line = format!("|{}|", frame.commands);
+        for (plugged, pad) in self.ports.iter().zip(frame.pads) {
+            if *plugged {
+                line.push_str(&format_fm2_pad(pad));
+            }
+            line.push('|');
+        }
+        line.push('|');
+        line
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut header = Vec::new();
+        self.write_header(&mut header)
+            .expect("writing to a vector does not fail");
+        let mut text = String::from_utf8(header).expect("the header is text");
+        for frame in &self.frames {
+            let _ = writeln!(text, "{}", self.format_frame(frame));
+        }
+        text
+    }
+
+    // The buttons of both pads for every frame
+    pub fn input_script(&self) -> InputScript {
+        InputScript::from_frames(self.frames.iter().map(|frame| frame.pads).collect())
+    }
+
+    // The commands for every frame
+    pub fn commands(&self) -> Vec<u8> {
+        self.frames.iter().map(|frame| frame.commands).collect()
+    }
+}
+
+#[derive(Debug)]
+struct Recording {
+    movie: Movie,
+    output: Option<BufWriter<File>>, // File the finished frames are appended to
+    written: usize,                  // Number of frames written to the file
+}
+
+// Records the input of both ports and the resets into a movie
+//
+// The recorder is shared between the sources of the devices, which record the buttons they
+// read, and the CPU, which records resets and tells it when a frame starts. A frame is appended
+// to the file once the next one starts, so a recording is kept when the window is closed.
+#[derive(Debug, Clone)]
+pub struct MovieRecorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl MovieRecorder {
+    pub fn new(movie: Movie) -> Self {
+        MovieRecorder {
+            recording: Arc::new(Mutex::new(Recording {
+                movie,
+                output: None,
+                written: 0,
+            })),
+        }
+    }
+
+    // Records into a new FM2 file
+    pub fn create(path: impl AsRef<Path>, movie: Movie) -> io::Result<Self> {
+        let mut output = BufWriter::new(File::create(path)?);
+        movie.write_header(&mut output)?;
+        let recorder = MovieRecorder::new(movie);
+        recorder.lock().output = Some(output);
+        Ok(recorder)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Recording> {
+        self.recording.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Wraps the source of a device so the buttons it provides are recorded for the port
+    pub fn source(&self, port: Port, source: Box<dyn InputSource>) -> RecordingInput {
+        RecordingInput {
+            recorder: self.clone(),
+            port,
+            source,
+            frame: 0,
+            sampled: None,
+        }
+    }
+
+    fn frame_mut(recording: &mut Recording, frame: u64) -> &mut MovieFrame {
+        let frame = usize::try_from(frame).unwrap_or(usize::MAX - 1);
+        if recording.movie.frames.len() <= frame {
+            recording
+                .movie
+                .frames
+                .resize(frame + 1, MovieFrame::default());
+        }
+        &mut recording.movie.frames[frame]
+    }
+
+    pub fn record_command(&self, frame: u64, command: u8) {
+        Self::frame_mut(&mut self.lock(), frame).commands |= command;
+    }
+
+    fn record_buttons(&self, frame: u64, port: Port, buttons: Buttons) {
+        Self::frame_mut(&mut self.lock(), frame).pads[port as usize] = buttons;
+    }
+
+    // Called when a frame starts, which finishes the frames before it
+    pub fn start_frame(&self, frame: u64) -> io::Result<()> {
+        let mut recording = self.lock();
+        Self::frame_mut(&mut recording, frame);
+        let finished = usize::try_from(frame).unwrap_or(usize::MAX);
+        write_frames(&mut recording, finished)
+    }
+
+    // Writes the frames that are left to the file
+    pub fn finish(&self) -> io::Result<()> {
+        let mut recording = self.lock();
+        let frames = recording.movie.frames.len();
+        write_frames(&mut recording, frames)
+    }
+
+    // Returns the movie recorded so far
+    pub fn movie(&self) -> Movie {
+        self.lock().movie.clone()
+    }
+}
+
+fn write_frames(recording: &mut Recording, end: usize) -> io::Result<()> {
+    let Recording {
+        movie,
+        output,
+        written,
+    } = recording;
+    if let Some(output) = output {
+        let end = end.min(movie.frames.len());
+        for frame in movie.frames.get(*written..end).unwrap_or_default() {
+            writeln!(output, "{}", movie.format_frame(frame))?;
+        }
+        *written = (*written).max(end);
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// A source that records the buttons it provides
+//
+// The buttons are read from the wrapped source once per frame, so a movie plays back exactly the
+// input the game saw.
+#[derive(Debug)]
+pub struct RecordingInput {
+    recorder: MovieRecorder,
+    port: Port,
+    source: Box<dyn InputSource>,
+    frame: u64,
+    sampled: Option<Buttons>, // The buttons of the current frame once they are read
+}
+
+impl InputSource for RecordingInput {
+    fn buttons(&mut self, ppu: &Ppu) -> Buttons {
+        match self.sampled {
+            Some(buttons) => buttons,
+            None => {
+                let buttons = self.source.buttons(ppu);
+                self.recorder.record_buttons(self.frame, self.port, buttons);
+                self.sampled = Some(buttons);
+                buttons
+            }
+        }
+    }
+
+    fn start_frame(&mut self, frame: u64) {
+        self.frame = frame;
+        self.sampled = None;
+        self.source.start_frame(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let mut movie = Movie::new("game", [7; 16]);
+        movie.ports = [true, false];
+        movie.frames.push(MovieFrame::default());
+        movie.frames.push(MovieFrame {
+            commands: COMMAND_RESET,
+            pads: [
+                Buttons {
+                    start: true,
+                    a: true,
+                    ..Buttons::default()
+                },
+                Buttons::default(),
+            ],
+        });
+        let text = movie.to_fm2();
+        assert!(text.contains("romChecksum base64:BwcHBwcHBwcHBwcHBwcHBw==\n"));
+        assert!(text.ends_with("|0|........|||\n|1|....T..A|||\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+    }
+
+    #[test]
+    fn test_unsupported_movies_are_rejected() {
+        let checksum = "romChecksum base64:BwcHBwcHBwcHBwcHBwcHBw==\n";
+        assert_eq!(
+            Movie::parse("version 3\n|0|........|||\n"),
+            Err(MovieError::MissingChecksum)
+        );
+        assert!(matches!(
+            Movie::parse(&format!("{}fourscore 1\n", checksum)),
+            Err(MovieError::Unsupported(_))
+        ));
+        assert!(matches!(
+            Movie::parse(&format!("{}port1 2\n", checksum)),
+            Err(MovieError::Unsupported(_))
+        ));
+        assert!(matches!(
+            Movie::parse(&format!("{}|x|........|||\n", checksum)),
+            Err(MovieError::InvalidLine(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_recorder_samples_once_per_frame() {
+        #[derive(Debug)]
+        struct Alternating(bool);
+        impl InputSource for Alternating {
+            fn buttons(&mut self, _ppu: &Ppu) -> Buttons {
+                self.0 = !self.0;
+                Buttons {
+                    a: self.0,
+                    ..Buttons::default()
+                }
+            }
+        }
+
+        let ppu = Ppu::new(tudelft_nes_ppu::Mirroring::Horizontal);
+        let recorder = MovieRecorder::new(Movie::new("game", [0; 16]));
+        let mut source = recorder.source(Port::Two, Box::new(Alternating(false)));
+        assert!(source.buttons(&ppu).a);
+        assert!(source.buttons(&ppu).a);
+        source.start_frame(1);
+        recorder.record_command(1, COMMAND_RESET);
+        assert!(!source.buttons(&ppu).a);
+
+        let movie = recorder.movie();
+        assert_eq!(movie.frames.len(), 2);
+        assert!(movie.frames[0].pads[1].a);
+        assert_eq!(movie.frames[1].commands, COMMAND_RESET);
+    }
+}
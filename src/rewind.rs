@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+// Frames in a second of NTSC video, rounded down
+pub const FRAMES_PER_SECOND: u64 = 60;
+
+// A save state stored as its difference to the state taken after it
+#[derive(Debug)]
+struct Delta {
+    frame: u64,    // Frame the state was taken at
+    length: usize, // Length of the state
+    runs: Vec<u8>, // XOR with the newer state, as pairs of a run of zeroes and a run of other bytes
+}
+
+impl Delta {
+    fn encode(frame: u64, older: &[u8], newer: &[u8]) -> Self {
+        let difference: Vec<u8> = older
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ newer.get(i).copied().unwrap_or(0))
+            .collect();
+        let mut runs = Vec::new();
+        let mut position = 0;
+        while position < difference.len() {
+            let zeroes = difference[position..]
+                .iter()
+                .take_while(|&&byte| byte == 0)
+                .count();
+            position += zeroes;
+            let changed = difference[position..]
+                .iter()
+                .take_while(|&&byte| byte != 0)
+                .count();
+            write_varint(&mut runs, zeroes);
+            write_varint(&mut runs, changed);
+            runs.extend_from_slice(&difference[position..position + changed]);
+            position += changed;
+        }
+        Delta {
+            frame,
+            length: older.len(),
+            runs,
+        }
+    }
+
+    // Turns the newer state back into the state this delta was encoded from
+    fn apply(&self, state: &mut Vec<u8>) {
+        state.resize(self.length, 0);
+        let mut runs = self.runs.as_slice();
+        let mut position = 0;
+        while !runs.is_empty() {
+            position += read_varint(&mut runs);
+            let changed = read_varint(&mut runs);
+            for (byte, difference) in state[position..position + changed].iter_mut().zip(runs) {
+                *byte ^= difference;
+            }
+            runs = &runs[changed..];
+            position += changed;
+        }
+    }
+}
+
+// Writes a number in 7 bit groups, least significant first, with the high bit set on all but the
+// last group
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+// A bounded ring of save states taken every few frames, which lets the emulator go back in time
+//
+// Only the newest state is kept whole. Every older state is stored as its difference to the
+// state after it, which is small as most of the memory does not change in a few frames. The
+// oldest states are dropped once the ring is full.
+#[derive(Debug)]
+pub struct RewindBuffer {
+    interval: u64,                  // Frames between two states
+    capacity: usize,                // Maximum number of states
+    newest: Option<(u64, Vec<u8>)>, // The newest state and the frame it was taken at
+    older: VecDeque<Delta>,         // The states before it, the oldest first
+}
+
+impl RewindBuffer {
+    // Creates a buffer taking a state every `interval` frames and keeping `capacity` of them
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            newest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    // Returns true when a state should be taken at the start of the frame
+    pub fn is_due(&self, frame: u64) -> bool {
+        frame.is_multiple_of(self.interval)
+            && self
+                .newest
+                .as_ref()
+                .is_none_or(|(newest, _)| *newest != frame)
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((newest_frame, newest)) = self.newest.take() {
+            self.older
+                .push_back(Delta::encode(newest_frame, &newest, &state));
+        }
+        self.newest = Some((frame, state));
+        while self.len() > self.capacity {
+            self.older.pop_front();
+        }
+    }
+
+    // Returns the newest state taken at or before a frame, or the oldest state when all of them
+    // are newer, and drops the states after it
+    pub fn rewind_to(&mut self, frame: u64) -> Option<(u64, Vec<u8>)> {
+        let (newest_frame, newest) = self.newest.as_mut()?;
+        while *newest_frame > frame {
+            let Some(delta) = self.older.pop_back() else {
+                break;
+            };
+            delta.apply(newest);
+            *newest_frame = delta.frame;
+        }
+        self.newest.clone()
+    }
+
+    // Returns the number of states in the buffer
+    pub fn len(&self) -> usize {
+        self.older.len() + usize::from(self.newest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Returns the number of bytes the states take up
+    pub fn size(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, |(_, state)| state.len());
+        newest
+            + self
+                .older
+                .iter()
+                .map(|delta| delta.runs.len())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let older: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        let mut newer = older.clone();
+        newer[3] ^= 0xFF;
+        newer[500..520].fill(0x42);
+        newer.push(1);
+
+        let delta = Delta::encode(0, &older, &newer);
+        assert!(delta.runs.len() < 40);
+        let mut state = newer;
+        delta.apply(&mut state);
+        assert_eq!(state, older);
+
+        let mut varint = Vec::new();
+        write_varint(&mut varint, 300);
+        assert_eq!(varint, [0xAC, 0x02]);
+        assert_eq!(read_varint(&mut varint.as_slice()), 300);
+    }
+
+    #[test]
+    fn test_rewind_restores_older_states() {
+        let state = |frame: u64| {
+            let mut state = vec![0x55; 100];
+            state[50] = frame as u8;
+            state
+        };
+        let mut buffer = RewindBuffer::new(10, 4);
+        for frame in (10..=60).step_by(10) {
+            assert!(buffer.is_due(frame));
+            buffer.push(frame, state(frame));
+        }
+        assert!(!buffer.is_due(60));
+        assert!(!buffer.is_due(65));
+        assert_eq!(buffer.len(), 4);
+        assert!(buffer.size() < 120);
+
+        assert_eq!(buffer.rewind_to(45), Some((40, state(40))));
+        assert_eq!(buffer.len(), 2);
+        // Frame 20 and before were dropped, the oldest state left is returned
+        assert_eq!(buffer.rewind_to(0), Some((30, state(30))));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(RewindBuffer::new(1, 1).rewind_to(0), None);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::error::SaveStateError;
+use std::path::PathBuf;
+
+// Identifies a save state, it is followed by the version of the format and the MD5 of the ROM
+const MAGIC: [u8; 4] = *b"NESS";
+
+// Has to be incremented whenever a component changes what it writes
+pub const VERSION: u16 = 1;
+
+// Writes the header that starts every save state
+pub fn write_header(state: &mut StateWriter, rom_checksum: [u8; 16]) {
+    state.write_array(&MAGIC);
+    state.write_u16(VERSION);
+    state.write_array(&rom_checksum);
+}
+
+// Checks that a save state has the current version and was saved with the same ROM
+pub fn read_header(state: &mut StateReader, rom_checksum: [u8; 16]) -> Result<(), SaveStateError> {
+    if state.read_array()? != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = state.read_u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    if state.read_array()? != rom_checksum {
+        return Err(SaveStateError::RomMismatch);
+    }
+    Ok(())
+}
+
+// Saving or loading a state file or rewinding, which the CPU does when the next frame starts
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateCommand {
+    Save(PathBuf),
+    Load(PathBuf),
+    Rewind, // Go back a second, see `Cpu::rewind`
+}
+
+// Serializes the state of a component into a save state
+//
+// Values are written little endian, in the order the component chooses. Loading has to read
+// them back in the same order.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    // Writes bytes without their length, for blocks that always have the same size
+    pub fn write_array(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Writes a block of bytes preceded by its length
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// Reads a save state written by `StateWriter`
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    // Reads bytes written by `write_array`
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let (bytes, rest) = self
+            .data
+            .split_first_chunk::<N>()
+            .ok_or(SaveStateError::UnexpectedEnd)?;
+        self.data = rest;
+        Ok(*bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    // Reads a block written by `write_bytes` into a buffer, which has to have the same length
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+        let length = self.read_u32()? as usize;
+        if length != buffer.len() {
+            return Err(SaveStateError::Mismatch(format!(
+                "block of {} bytes where {} were expected",
+                length,
+                buffer.len()
+            )));
+        }
+        if self.data.len() < length {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        buffer.copy_from_slice(bytes);
+        self.data = rest;
+        Ok(())
+    }
+
+    // Returns true when all of the state has been read
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u32(0x789A_BCDE);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.read_u64(), Ok(u64::MAX - 1));
+        let mut buffer = [0; 3];
+        reader.read_bytes_into(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3]);
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_u8(), Err(SaveStateError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_header() {
+        let mut writer = StateWriter::new();
+        write_header(&mut writer, [1; 16]);
+        let data = writer.into_bytes();
+        assert_eq!(&data[..4], b"NESS");
+        assert_eq!(read_header(&mut StateReader::new(&data), [1; 16]), Ok(()));
+        assert_eq!(
+            read_header(&mut StateReader::new(&data), [2; 16]),
+            Err(SaveStateError::RomMismatch)
+        );
+        assert_eq!(
+            read_header(&mut StateReader::new(b"NES\x1a\x01\x01"), [1; 16]),
+            Err(SaveStateError::NotASaveState)
+        );
+
+        let mut newer = data.clone();
+        newer[4] = VERSION as u8 + 1;
+        assert_eq!(
+            read_header(&mut StateReader::new(&newer), [1; 16]),
+            Err(SaveStateError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn test_block_length_must_match() {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&[0; 4]);
+        let data = writer.into_bytes();
+        let mut buffer = [0; 8];
+        assert!(matches!(
+            StateReader::new(&data).read_bytes_into(&mut buffer),
+            Err(SaveStateError::Mismatch(_))
+        ));
+    }
+}